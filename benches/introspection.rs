@@ -0,0 +1,46 @@
+// Benchmark the introspection parser on a corpus of documents taken
+// from common services, and on a synthetic node with a very large
+// number of children.
+#[path = "../src/xml.rs"]
+#[allow(dead_code)]
+mod xml;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::{fmt::Write, fs, path::Path};
+
+fn large_node(children: usize) -> String {
+    let mut s = String::from("<node>\n");
+    s.push_str("  <interface name=\"org.freedesktop.DBus.Introspectable\">\n");
+    s.push_str("    <method name=\"Introspect\">\n");
+    s.push_str("      <arg name=\"xml_data\" type=\"s\" direction=\"out\"/>\n");
+    s.push_str("    </method>\n");
+    s.push_str("  </interface>\n");
+    for i in 0..children {
+        let _ = writeln!(s, "  <node name=\"unit_{}\"/>", i);
+    }
+    s.push_str("</node>\n");
+    s
+}
+
+fn parse(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches").join("corpus");
+    let mut corpus = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| {
+            let path = e.unwrap().path();
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    corpus.sort();
+    corpus.push((String::from("children-10000"), large_node(10000)));
+    let mut group = c.benchmark_group("parse");
+    for (name, doc) in &corpus {
+        group.throughput(Throughput::Bytes(doc.len() as u64));
+        group.bench_function(name.as_str(), |b| b.iter(|| doc.parse::<xml::Node>().unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
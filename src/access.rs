@@ -0,0 +1,430 @@
+use crate::audit::{self, Op};
+use anyhow::{anyhow, bail, Result};
+use futures::{
+    channel::mpsc::{self, Receiver, Sender, UnboundedReceiver},
+    prelude::*,
+    select_biased,
+};
+use fxhash::{FxHashMap, FxHashSet};
+use globset::{Glob, GlobMatcher};
+use log::{info, warn};
+use netidx::{
+    path::Path,
+    pool::Pooled,
+    publisher::{ClId, Event, Id, Publisher, Val, WriteRequest},
+    subscriber::Value,
+};
+use std::{
+    collections::HashMap,
+    fmt, result,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+};
+use tokio::{task, time::Instant};
+
+/// A netidx user or group that a client has logged in as
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+impl Principal {
+    fn kind(&self) -> &'static str {
+        match self {
+            Principal::User(_) => "user",
+            Principal::Group(_) => "group",
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Principal::User(n) | Principal::Group(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.kind(), self.name())
+    }
+}
+
+/// An access rule is written as `allow` or `deny` followed by fields
+/// that must all match, e.g. `allow group=wheel
+/// name=org.freedesktop.login1` or `deny op=call member=Power*`.
+/// `user` and `group` match clients that have logged in as them, `op`
+/// is one of call, set, or activate, and the rest are globs as in
+/// rules. A field the rule doesn't mention matches anything.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    text: String,
+    allow: bool,
+    who: Option<Principal>,
+    op: Option<Op>,
+    name: Option<GlobMatcher>,
+    path: Option<GlobMatcher>,
+    interface: Option<GlobMatcher>,
+    member: Option<GlobMatcher>,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let allow = match parts.next() {
+            None => bail!("empty access rule"),
+            Some("allow") => true,
+            Some("deny") => false,
+            Some(a) => bail!("unknown verdict {}, expected allow or deny", a),
+        };
+        let mut rule = Rule {
+            text: s.split_whitespace().collect::<Vec<_>>().join(" "),
+            allow,
+            who: None,
+            op: None,
+            name: None,
+            path: None,
+            interface: None,
+            member: None,
+        };
+        for part in parts {
+            let (key, v) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected <field>=<value>, got {}", part))?;
+            let glob = || -> Result<_> { Ok(Some(Glob::new(v)?.compile_matcher())) };
+            match key {
+                "user" => rule.who = Some(Principal::User(String::from(v))),
+                "group" => rule.who = Some(Principal::Group(String::from(v))),
+                "op" => {
+                    rule.op = Some(match v {
+                        "call" => Op::Call,
+                        "set" => Op::Set,
+                        "activate" => Op::Activate,
+                        o => bail!("unknown op {}, expected call, set, or activate", o),
+                    })
+                }
+                "name" => rule.name = glob()?,
+                "path" => rule.path = glob()?,
+                "interface" => rule.interface = glob()?,
+                "member" => rule.member = glob()?,
+                k => bail!(
+                    "unknown field {}, expected user, group, op, name, path, interface, or member",
+                    k
+                ),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Rule {
+    fn matches(
+        &self,
+        who: &[Principal],
+        op: Op,
+        name: &str,
+        path: Option<&str>,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> bool {
+        fn is_match(glob: &Option<GlobMatcher>, v: Option<&str>) -> bool {
+            match (glob, v) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(glob), Some(v)) => glob.is_match(v),
+            }
+        }
+        self.who.as_ref().map(|p| who.contains(p)).unwrap_or(true)
+            && self.op.map(|o| o == op).unwrap_or(true)
+            && is_match(&self.name, Some(name))
+            && is_match(&self.path, path)
+            && is_match(&self.interface, interface)
+            && is_match(&self.member, member)
+    }
+}
+
+/// An ordered list of access rules, the first rule that matches
+/// decides, and if none match the operation is allowed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Access(Vec<Rule>);
+
+impl Access {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Access(rules)
+    }
+
+    /// The users and groups the rules mention
+    pub fn principals(&self) -> FxHashSet<Principal> {
+        self.0.iter().filter_map(|r| r.who.clone()).collect()
+    }
+
+    /// Decide if a client logged in as `who` may perform `op`. An
+    /// activation only has a `name`, and a set's member is the
+    /// property. The error says which rule denied it.
+    pub fn check(
+        &self,
+        who: &[Principal],
+        op: Op,
+        name: &str,
+        path: Option<&str>,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> result::Result<(), String> {
+        match self
+            .0
+            .iter()
+            .find(|r| r.matches(who, op, name, path, interface, member))
+        {
+            Some(r) if !r.allow => Err(format!("permission denied by access rule `{}`", r)),
+            Some(_) | None => Ok(()),
+        }
+    }
+}
+
+/// A client, and the users and groups it has logged in as
+#[derive(Debug, Clone)]
+pub struct Who {
+    pub client: ClId,
+    pub principals: Vec<Principal>,
+}
+
+impl fmt::Display for Who {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.client)?;
+        for p in &self.principals {
+            write!(f, " {}", p)?
+        }
+        Ok(())
+    }
+}
+
+struct Inner {
+    publisher: Publisher,
+    base: Path,
+    tx_writes: Sender<Pooled<Vec<WriteRequest>>>,
+    by_principal: FxHashMap<Principal, Val>,
+    by_id: FxHashMap<Id, Principal>,
+    clients: FxHashMap<ClId, Vec<Principal>>,
+}
+
+impl Inner {
+    fn login(&mut self, id: Id, client: ClId) -> Option<Principal> {
+        let p = self.by_id.get(&id)?;
+        // it may have gone away while the write was queued
+        if !self.publisher.subscribed(&id).contains(&client) {
+            return None;
+        }
+        let logins = self.clients.entry(client).or_default();
+        if !logins.contains(p) {
+            logins.push(p.clone())
+        }
+        Some(p.clone())
+    }
+
+    fn who(&self, client: ClId) -> Who {
+        let principals = self.clients.get(&client).cloned().unwrap_or_default();
+        Who { client, principals }
+    }
+
+    fn logout(&mut self, id: Id, client: ClId) {
+        if let (Some(p), Some(logins)) = (self.by_id.get(&id), self.clients.get_mut(&client)) {
+            logins.retain(|l| l != p);
+            if logins.is_empty() {
+                self.clients.remove(&client);
+            }
+        }
+    }
+}
+
+/// netidx doesn't tell publishers which user is behind a connection,
+/// but the resolver does decide who may write to a path. So a login
+/// value is published for each user and group access rules mention,
+/// at `<base>/<user|group>/<name>`, and a client that writes to one,
+/// which the resolver should only let that user or group do, is
+/// logged in as it until it unsubscribes.
+#[derive(Clone)]
+pub struct Logins(Arc<Mutex<Inner>>);
+
+impl Logins {
+    pub fn new(publisher: Publisher, base: Path, audit: audit::Audit) -> Self {
+        let (tx_events, rx_events) = mpsc::unbounded();
+        let (tx_writes, rx_writes) = mpsc::channel(3);
+        publisher.events(tx_events);
+        let inner = Arc::new(Mutex::new(Inner {
+            publisher,
+            base,
+            tx_writes,
+            by_principal: HashMap::default(),
+            by_id: HashMap::default(),
+            clients: HashMap::default(),
+        }));
+        task::spawn(Self::run(
+            Arc::downgrade(&inner),
+            audit,
+            rx_events,
+            rx_writes,
+        ));
+        Logins(inner)
+    }
+
+    /// Publish a login value for every user and group `access`
+    /// mentions, and log everyone out of the rest
+    pub fn configure(&self, access: &Access) {
+        let mut inner = self.0.lock().unwrap();
+        let inner = &mut *inner;
+        let principals = access.principals();
+        inner.by_principal.retain(|p, val| {
+            let keep = principals.contains(p);
+            if !keep {
+                inner.by_id.remove(&val.id());
+            }
+            keep
+        });
+        for logins in inner.clients.values_mut() {
+            logins.retain(|p| principals.contains(p))
+        }
+        inner.clients.retain(|_, logins| !logins.is_empty());
+        for p in principals {
+            if inner.by_principal.contains_key(&p) {
+                continue;
+            }
+            let path = inner.base.append(p.kind()).append(p.name());
+            let doc = format!("write to log in as {}", p);
+            match inner.publisher.publish(path.clone(), Value::from(doc)) {
+                Err(e) => warn!("failed to publish login {} {}", path, e),
+                Ok(val) => {
+                    inner.publisher.writes(val.id(), inner.tx_writes.clone());
+                    inner.by_id.insert(val.id(), p.clone());
+                    inner.by_principal.insert(p, val);
+                }
+            }
+        }
+    }
+
+    /// The users and groups `client` is logged in as
+    pub fn who(&self, client: ClId) -> Who {
+        self.0.lock().unwrap().who(client)
+    }
+
+    async fn run(
+        inner: Weak<Mutex<Inner>>,
+        audit: audit::Audit,
+        mut events: UnboundedReceiver<Event>,
+        mut writes: Receiver<Pooled<Vec<WriteRequest>>>,
+    ) {
+        loop {
+            select_biased! {
+                e = events.next() => match (e, inner.upgrade()) {
+                    (Some(Event::Unsubscribe(id, client)), Some(inner)) => {
+                        inner.lock().unwrap().logout(id, client)
+                    }
+                    (Some(_), Some(_)) => (),
+                    (None, _) | (_, None) => break,
+                },
+                reqs = writes.next() => match (reqs, inner.upgrade()) {
+                    (Some(mut reqs), Some(inner)) => {
+                        for req in reqs.drain(..) {
+                            let start = Instant::now();
+                            let mut inner = inner.lock().unwrap();
+                            if let Some(p) = inner.login(req.id, req.client) {
+                                info!("{:?} logged in as {}", req.client, p);
+                                let who = inner.who(req.client);
+                                let (op, target) = (Op::Login, p.to_string());
+                                audit.record(who, op, target, String::new(), start, Ok(String::from("ok")));
+                            }
+                        }
+                    }
+                    (None, _) | (_, None) => break,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(rules: &[&str]) -> Access {
+        Access::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn check() {
+        let access = access(&[
+            "allow group=wheel name=org.freedesktop.login1",
+            "deny name=org.freedesktop.login1",
+            "deny user=guest op=set",
+            "deny op=call interface=org.freedesktop.systemd1.Manager member=Power*",
+        ]);
+        let wheel = [Principal::Group(String::from("wheel"))];
+        let guest = [Principal::User(String::from("guest"))];
+        let login1 = "org.freedesktop.login1";
+        let (path, iface) = (
+            Some("/org/freedesktop/login1"),
+            Some("org.freedesktop.login1.Manager"),
+        );
+        assert!(access
+            .check(&wheel, Op::Call, login1, path, iface, Some("PowerOff"))
+            .is_ok());
+        assert!(access
+            .check(&[], Op::Call, login1, path, iface, Some("PowerOff"))
+            .is_err());
+        assert!(access
+            .check(&[], Op::Activate, login1, None, None, None)
+            .is_err());
+        let systemd = "org.freedesktop.systemd1";
+        let iface = Some("org.freedesktop.systemd1.Manager");
+        assert!(access
+            .check(&wheel, Op::Call, systemd, path, iface, Some("PowerOff"))
+            .is_err());
+        assert!(access
+            .check(&wheel, Op::Call, systemd, path, iface, Some("Reload"))
+            .is_ok());
+        assert!(access
+            .check(&guest, Op::Set, systemd, path, iface, Some("LogLevel"))
+            .is_err());
+        assert!(access
+            .check(&wheel, Op::Set, systemd, path, iface, Some("LogLevel"))
+            .is_ok());
+        let e = access
+            .check(&[], Op::Call, login1, path, iface, None)
+            .unwrap_err();
+        assert_eq!(
+            e,
+            "permission denied by access rule `deny name=org.freedesktop.login1`"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert!("".parse::<Rule>().is_err());
+        assert!("permit name=a".parse::<Rule>().is_err());
+        assert!("deny op=login".parse::<Rule>().is_err());
+        assert!("deny who=a".parse::<Rule>().is_err());
+        assert!("deny name".parse::<Rule>().is_err());
+        let access = access(&["allow  user=alice", "deny group=adm", "deny"]);
+        let principals = access.principals();
+        assert_eq!(principals.len(), 2);
+        assert!(principals.contains(&Principal::User(String::from("alice"))));
+        assert!(principals.contains(&Principal::Group(String::from("adm"))));
+        assert_eq!(
+            "allow  user=alice".parse::<Rule>().unwrap().to_string(),
+            "allow user=alice"
+        );
+    }
+}
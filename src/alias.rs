@@ -0,0 +1,104 @@
+use anyhow::{anyhow, bail, Result};
+use netidx::path::Path;
+use std::str::FromStr;
+
+/// Publishes a property, signal, or method at a short path as well
+/// as in the tree, written as
+/// `<path>=<bus name>:<object path>:<interface>.<member>`. Since the
+/// alias is found by bus name, it follows the object when the owner
+/// of a well known name changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    path: Path,
+    name: String,
+    object: String,
+    interface: String,
+    member: String,
+}
+
+impl FromStr for Alias {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (path, target) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <path>=<bus name>:<object path>:<member>"))?;
+        // unique names start with a :, but an object path never
+        // contains one, so the name ends at the first :/
+        let parts = target
+            .find(":/")
+            .and_then(|i| Some((&target[..i], target[i + 1..].split_once(':')?)));
+        let (name, (object, member)) = match parts {
+            Some((name, rest)) if !name.is_empty() => (name, rest),
+            _ => bail!(
+                "expected <bus name>:<object path>:<interface>.<member>, got {}",
+                target
+            ),
+        };
+        let (interface, member) = member
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow!("expected <interface>.<member>, got {}", member))?;
+        if !Path::is_absolute(path) {
+            bail!("alias path {} must be absolute", path)
+        }
+        if !object.starts_with('/') {
+            bail!("invalid object path {}", object)
+        }
+        Ok(Alias {
+            path: Path::from(String::from(path)),
+            name: String::from(name),
+            object: String::from(object),
+            interface: String::from(interface),
+            member: String::from(member),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aliases(Vec<Alias>);
+
+impl Aliases {
+    pub fn new(aliases: Vec<Alias>) -> Self {
+        Aliases(aliases)
+    }
+
+    /// The paths `member` of `interface` on `object` of bus name
+    /// `name` is aliased to
+    pub fn find<'a>(
+        &'a self,
+        name: &'a str,
+        object: &'a str,
+        interface: &'a str,
+        member: &'a str,
+    ) -> impl Iterator<Item = &'a Path> + 'a {
+        self.0
+            .iter()
+            .filter(move |a| {
+                a.name == name
+                    && a.object == object
+                    && a.interface == interface
+                    && a.member == member
+            })
+            .map(|a| &a.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let a = "/a=org.example.Foo:/org/example:org.example.Foo.Bar"
+            .parse::<Alias>()
+            .unwrap();
+        assert_eq!((&*a.name, &*a.object), ("org.example.Foo", "/org/example"));
+        assert_eq!((&*a.interface, &*a.member), ("org.example.Foo", "Bar"));
+        let a = "/a=:1.42:/obj:org.example.Foo.Bar"
+            .parse::<Alias>()
+            .unwrap();
+        assert_eq!((&*a.name, &*a.object), (":1.42", "/obj"));
+        assert!("/a=:1.42:obj:org.example.Foo.Bar".parse::<Alias>().is_err());
+        assert!("/a=:/obj:org.example.Foo.Bar".parse::<Alias>().is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{channel::mpsc, prelude::*};
+use log::warn;
+use netidx::{
+    chars::Chars,
+    path::Path,
+    publisher::{Publisher, Val},
+    subscriber::Value,
+};
+use std::{collections::VecDeque, fmt, path::PathBuf, result, time::Duration};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    task,
+    time::Instant,
+};
+
+// the log file is rotated when it reaches this size, and this many
+// old logs are kept
+const LOG_MAX: u64 = 10 * 1024 * 1024;
+const LOG_KEEP: usize = 5;
+
+// the number of recent operations kept in the table
+const ROWS: usize = 100;
+
+const COLUMNS: [&str; 7] = [
+    "time", "client", "op", "target", "args", "result", "latency",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Call,
+    Set,
+    Activate,
+    Login,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Call => "call",
+            Op::Set => "set",
+            Op::Activate => "activate",
+            Op::Login => "login",
+        }
+    }
+}
+
+/// One state changing operation. netidx doesn't tell publishers who
+/// the user behind a connection is, so the client is identified by
+/// it's connection, and the users and groups it has logged in as.
+#[derive(Debug, Serialize)]
+struct Record {
+    time: DateTime<Utc>,
+    client: String,
+    op: Op,
+    target: String,
+    args: String,
+    result: result::Result<String, String>,
+    latency: f64,
+}
+
+impl Record {
+    fn columns(&self) -> [Value; 7] {
+        [
+            Value::DateTime(self.time),
+            Value::from(self.client.clone()),
+            Value::from(self.op.name()),
+            Value::from(self.target.clone()),
+            Value::from(self.args.clone()),
+            match &self.result {
+                Ok(v) => Value::from(v.clone()),
+                Err(e) => Value::Error(Chars::from(e.clone())),
+            },
+            Value::from(self.latency),
+        ]
+    }
+}
+
+/// A log file that is rotated when it gets too big
+struct Log {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl Log {
+    async fn open(path: PathBuf) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Log { path, file, size })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        for n in (1..LOG_KEEP).rev() {
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1)).await;
+        }
+        fs::rename(&self.path, self.rotated(1)).await?;
+        *self = Log::open(self.path.clone()).await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if self.size > 0 && self.size + line.len() as u64 > LOG_MAX {
+            self.rotate().await?
+        }
+        self.file.write_all(&line).await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+enum Msg {
+    Record(Record),
+    Configure(Option<PathBuf>, Option<Duration>),
+}
+
+/// Records every method call, property set, service activation, and
+/// login made through the bridge, to a rotating log file if one is
+/// given, and to a table of the most recent ones under `base`, newest
+/// first.
+#[derive(Clone)]
+pub struct Audit(mpsc::UnboundedSender<Msg>);
+
+impl Audit {
+    pub fn new(
+        publisher: Publisher,
+        base: Path,
+        file: Option<PathBuf>,
+        timeout: Option<Duration>,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        task::spawn(Self::run(publisher, base, file, timeout, rx));
+        Audit(tx)
+    }
+
+    /// Record an operation on `target` that started at `start`
+    pub fn record(
+        &self,
+        client: impl fmt::Display,
+        op: Op,
+        target: String,
+        args: String,
+        start: Instant,
+        result: result::Result<String, String>,
+    ) {
+        let _ = self.0.unbounded_send(Msg::Record(Record {
+            time: Utc::now(),
+            client: client.to_string(),
+            op,
+            target,
+            args,
+            result,
+            latency: start.elapsed().as_secs_f64(),
+        }));
+    }
+
+    /// Switch to a new log file and timeout, the table is kept
+    pub fn configure(&self, file: Option<PathBuf>, timeout: Option<Duration>) {
+        let _ = self.0.unbounded_send(Msg::Configure(file, timeout));
+    }
+
+    async fn open(file: &Option<PathBuf>) -> Option<Log> {
+        let file = file.as_ref()?;
+        match Log::open(file.clone()).await {
+            Ok(log) => Some(log),
+            Err(e) => {
+                warn!("failed to open audit log {} {}", file.display(), e);
+                None
+            }
+        }
+    }
+
+    async fn run(
+        publisher: Publisher,
+        base: Path,
+        mut file: Option<PathBuf>,
+        mut timeout: Option<Duration>,
+        mut rx: mpsc::UnboundedReceiver<Msg>,
+    ) {
+        let mut log = Self::open(&file).await;
+        let mut records = VecDeque::new();
+        // rows are published as they are needed
+        let mut rows: Vec<Vec<Val>> = Vec::new();
+        while let Some(msg) = rx.next().await {
+            let record = match msg {
+                Msg::Record(record) => record,
+                Msg::Configure(new_file, new_timeout) => {
+                    if new_file != file {
+                        log = Self::open(&new_file).await;
+                        file = new_file;
+                    }
+                    timeout = new_timeout;
+                    continue;
+                }
+            };
+            if let Some(log) = &mut log {
+                if let Err(e) = log.write(&record).await {
+                    warn!("failed to write audit log {}", e)
+                }
+            }
+            records.push_front(record);
+            records.truncate(ROWS);
+            while rows.len() < records.len() {
+                let row = base.append(&format!("{:03}", rows.len()));
+                let vals = COLUMNS
+                    .iter()
+                    .map(|c| publisher.publish(row.append(c), Value::Null))
+                    .collect::<Result<Vec<_>, _>>();
+                match vals {
+                    Ok(vals) => rows.push(vals),
+                    Err(e) => {
+                        warn!("failed to publish audit table row {} {}", row, e);
+                        break;
+                    }
+                }
+            }
+            let mut batch = publisher.start_batch();
+            for (row, record) in rows.iter().zip(records.iter()) {
+                for (val, v) in row.iter().zip(record.columns()) {
+                    val.update_changed(&mut batch, v)
+                }
+            }
+            batch.commit(timeout).await
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::xml;
+use anyhow::Result;
+use fxhash::FxHashMap;
+use log::warn;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tokio::fs;
+
+/// Identifies the program that owns a bus name. If the program
+/// changes, e.g. because it was upgraded, then it's cached
+/// introspection data is discarded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    exe: Option<(PathBuf, u64)>,
+    service: Option<(PathBuf, u64)>,
+}
+
+async fn mtime(path: &Path) -> Option<u64> {
+    let md = fs::metadata(path).await.ok()?;
+    Some(
+        md.modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+    )
+}
+
+fn service_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    match env::var_os("XDG_DATA_HOME") {
+        Some(d) => dirs.push(PathBuf::from(d)),
+        None => {
+            if let Some(home) = env::var_os("HOME") {
+                dirs.push(PathBuf::from(home).join(".local/share"))
+            }
+        }
+    }
+    match env::var("XDG_DATA_DIRS") {
+        Ok(d) => dirs.extend(d.split(':').map(PathBuf::from)),
+        Err(_) => dirs.extend(["/usr/local/share", "/usr/share"].iter().map(PathBuf::from)),
+    }
+    dirs.into_iter()
+        .flat_map(|d| [d.join("dbus-1/services"), d.join("dbus-1/system-services")])
+        .collect()
+}
+
+impl Owner {
+    /// Identify the owner of bus name `name` by the executable of
+    /// it's process (if `pid` is known), and by it's service file,
+    /// if it has one.
+    pub async fn new(name: &str, pid: Option<u32>) -> Self {
+        let exe = match pid {
+            None => None,
+            Some(pid) => match fs::read_link(format!("/proc/{}/exe", pid)).await {
+                Err(_) => None,
+                Ok(exe) => mtime(&exe).await.map(|m| (exe, m)),
+            },
+        };
+        let mut service = None;
+        for dir in service_dirs() {
+            let path = dir.join(format!("{}.service", name));
+            if let Some(m) = mtime(&path).await {
+                service = Some((path, m));
+                break;
+            }
+        }
+        Owner { exe, service }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    owner: Owner,
+    objects: FxHashMap<String, String>,
+}
+
+/// An on disk cache of introspection data, keyed by bus, bus name,
+/// then object path. It is used to publish the tree immediately at
+/// startup, the tree is then revalidated in the background.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub async fn new(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        Ok(Cache { dir })
+    }
+
+    /// The cache of the bus published at `base`. Buses have their own
+    /// entries, because the same name, e.g. on the session bus of
+    /// different users, is usually owned by different programs.
+    pub fn bus(&self, base: &str) -> Self {
+        let dir = base.replace('%', "%25").replace('/', "%2F");
+        Cache {
+            dir: self.dir.join(dir),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// Load the cached introspection data for `name`, if there is
+    /// any, and it was produced by the same owner.
+    pub async fn load(&self, name: &str, owner: &Owner) -> Option<FxHashMap<String, xml::Node>> {
+        let data = fs::read(self.path(name)).await.ok()?;
+        let entry: Entry = match serde_json::from_slice(&data) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("invalid cache entry for {} {}", name, e);
+                return None;
+            }
+        };
+        if &entry.owner != owner {
+            return None;
+        }
+        let mut objects = FxHashMap::default();
+        for (path, doc) in entry.objects {
+            match doc.parse::<xml::Node>() {
+                Ok(node) => {
+                    objects.insert(path, node);
+                }
+                Err(e) => {
+                    warn!("invalid cached introspection for {}:{} {}", name, path, e);
+                    return None;
+                }
+            }
+        }
+        Some(objects)
+    }
+
+    pub async fn store(
+        &self,
+        name: &str,
+        owner: Owner,
+        objects: FxHashMap<String, String>,
+    ) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(name);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&Entry { owner, objects })?).await?;
+        Ok(fs::rename(&tmp, &path).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        process,
+        time::{Duration, SystemTime},
+    };
+
+    fn objects() -> FxHashMap<String, String> {
+        let mut objects = FxHashMap::default();
+        objects.insert(String::from("/"), xml::Node::new(None).to_xml());
+        objects
+    }
+
+    #[tokio::test]
+    async fn invalidation() {
+        let dir = env::temp_dir().join(format!("netidx-dbus-cache-test-{}", process::id()));
+        let data = dir.join("data");
+        let services = data.join("dbus-1/services");
+        std::fs::create_dir_all(&services).unwrap();
+        env::set_var("XDG_DATA_HOME", &data);
+        env::set_var("XDG_DATA_DIRS", &data);
+        let service = services.join("org.example.Test.service");
+        let touch = |secs| {
+            let file = File::create(&service).unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            file.set_modified(mtime).unwrap();
+        };
+        touch(1000);
+        let owner = Owner::new("org.example.Test", None).await;
+        assert_eq!(owner.service, Some((service.clone(), 1000)));
+        let cache = Cache::new(dir.join("cache")).await.unwrap();
+        let bus = cache.bus("/local/dbus/session");
+        bus.store("org.example.Test", owner.clone(), objects())
+            .await
+            .unwrap();
+        let loaded = bus.load("org.example.Test", &owner).await.unwrap();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), ["/"]);
+        // entries are kept by bus and by name
+        let system = cache.bus("/local/dbus/system");
+        assert!(system.load("org.example.Test", &owner).await.is_none());
+        assert!(bus.load("org.example.Other", &owner).await.is_none());
+        // the service file was upgraded
+        touch(2000);
+        let upgraded = Owner::new("org.example.Test", None).await;
+        assert_ne!(upgraded, owner);
+        assert!(bus.load("org.example.Test", &upgraded).await.is_none());
+        // the name is owned by a different program
+        let other = Owner {
+            exe: Some((PathBuf::from("/usr/bin/other"), 1000)),
+            ..owner.clone()
+        };
+        assert!(bus.load("org.example.Test", &other).await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Bridge settings read from a config file, in TOML, or JSON if the
+/// file name ends in `.json`. Keys are named after the command line
+/// options they replace, anything that isn't set falls back to the
+/// command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub netidx_base: Option<String>,
+    pub timeout: Option<u64>,
+    pub system: Option<bool>,
+    pub address: Option<Vec<String>>,
+    pub peer: Option<Vec<String>>,
+    pub users: Option<bool>,
+    pub cache_dir: Option<PathBuf>,
+    pub unique: Option<bool>,
+    pub unique_filter: Option<Vec<String>>,
+    pub rules: Option<Vec<String>>,
+    pub rules_file: Option<PathBuf>,
+    pub aliases: Option<Vec<String>>,
+    pub read_only: Option<bool>,
+    pub access: Option<Vec<String>>,
+    pub audit_log: Option<PathBuf>,
+    pub redact: Option<Vec<String>>,
+    pub default_redactions: Option<bool>,
+    pub own_name: Option<String>,
+    pub lazy_properties: Option<bool>,
+    pub max_introspections: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_objects: Option<usize>,
+    pub call_timeout: Option<u64>,
+    pub settle_max: Option<u64>,
+    pub retained_grace: Option<u64>,
+}
+
+impl Config {
+    pub async fn load(file: &Path) -> Result<Self> {
+        let data = fs::read_to_string(file).await?;
+        match file.extension().and_then(|e| e.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&data)?),
+            _ => Ok(toml::from_str(&data)?),
+        }
+    }
+}
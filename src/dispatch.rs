@@ -0,0 +1,350 @@
+use anyhow::Result;
+use dbus::{
+    channel::{MatchingReceiver, Token},
+    message::{MatchRule, MessageType},
+    nonblock::SyncConnection,
+    Message,
+};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use fxhash::FxHashMap;
+use std::sync::{Arc, Mutex};
+
+/// Where a signal is published from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Route {
+    path: String,
+    interface: String,
+    member: String,
+}
+
+struct Subscriber {
+    id: u64,
+    // the unique name of the sender, None to accept any sender
+    sender: Option<Arc<str>>,
+    tx: UnboundedSender<Message>,
+}
+
+// subscribers by path, interface, and member, nested so a signal can
+// be looked up without copying it's route
+type ByRoute = FxHashMap<String, FxHashMap<String, FxHashMap<String, Vec<Subscriber>>>>;
+
+#[derive(Default)]
+struct Routes {
+    by_route: ByRoute,
+    by_id: FxHashMap<u64, Route>,
+    next: u64,
+}
+
+impl Routes {
+    fn get(&self, path: &str, interface: &str, member: &str) -> Option<&Vec<Subscriber>> {
+        self.by_route.get(path)?.get(interface)?.get(member)
+    }
+
+    fn insert(&mut self, route: Route, sub: Subscriber) {
+        self.by_route
+            .entry(route.path)
+            .or_default()
+            .entry(route.interface)
+            .or_default()
+            .entry(route.member)
+            .or_default()
+            .push(sub)
+    }
+
+    fn remove(&mut self, route: &Route, id: u64) {
+        if let Some(interfaces) = self.by_route.get_mut(&route.path) {
+            if let Some(members) = interfaces.get_mut(&route.interface) {
+                if let Some(subs) = members.get_mut(&route.member) {
+                    subs.retain(|s| s.id != id);
+                    if subs.is_empty() {
+                        members.remove(&route.member);
+                    }
+                }
+                if members.is_empty() {
+                    interfaces.remove(&route.interface);
+                }
+            }
+            if interfaces.is_empty() {
+                self.by_route.remove(&route.path);
+            }
+        }
+    }
+
+    fn dispatch(&self, msg: Message) {
+        let subs = match (msg.path(), msg.interface(), msg.member()) {
+            (Some(path), Some(interface), Some(member)) => {
+                match self.get(&path, &interface, &member) {
+                    None => return,
+                    Some(subs) => subs,
+                }
+            }
+            (_, _, _) => return,
+        };
+        let sender = msg.sender();
+        let subs = subs
+            .iter()
+            .filter(|s| match (&s.sender, &sender) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(s), Some(sender)) => **s == **sender,
+            })
+            .collect::<Vec<_>>();
+        if let Some((last, rest)) = subs.split_last() {
+            for sub in rest {
+                if let Ok(msg) = msg.duplicate() {
+                    let _ = sub.tx.unbounded_send(msg);
+                }
+            }
+            let _ = last.tx.unbounded_send(msg);
+        }
+    }
+}
+
+/// The bus name, interface, and member a bus rule matches
+type Rule = (String, String, String);
+
+/// How many subscriptions use each bus rule
+#[derive(Default)]
+struct Rules(FxHashMap<Rule, usize>);
+
+impl Rules {
+    /// Count a subscription to `rule`, true if it is the first
+    fn add(&mut self, rule: &Rule) -> bool {
+        match self.0.get_mut(rule) {
+            Some(n) => {
+                *n += 1;
+                false
+            }
+            None => {
+                self.0.insert(rule.clone(), 1);
+                true
+            }
+        }
+    }
+
+    /// Forget a subscription to `rule`, true if it was the last
+    fn remove(&mut self, rule: &Rule) -> bool {
+        match self.0.get_mut(rule) {
+            None => false,
+            Some(n) if *n > 1 => {
+                *n -= 1;
+                false
+            }
+            Some(_) => {
+                self.0.remove(rule);
+                true
+            }
+        }
+    }
+}
+
+/// A subscription to a signal, pass it to `Dispatcher::unsubscribe`
+/// when it is no longer wanted
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    rule: Option<Rule>,
+}
+
+/// Routes the signals of one connection to the tasks that publish
+/// them. Instead of a match rule for every signal of every object, the
+/// bus daemon is asked for each signal of a bus name that has a
+/// subscriber, from any object, and they are demultiplexed here by
+/// path. A peer sends us everything, so there are no bus rules.
+pub struct Dispatcher {
+    con: Arc<SyncConnection>,
+    peer: bool,
+    token: Token,
+    routes: Arc<Mutex<Routes>>,
+    rules: tokio::sync::Mutex<Rules>,
+}
+
+impl Dispatcher {
+    pub fn new(con: Arc<SyncConnection>, peer: bool) -> Self {
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let token = {
+            let routes = Arc::clone(&routes);
+            let mut rule = MatchRule::new();
+            rule.msg_type = Some(MessageType::Signal);
+            con.start_receive(
+                rule,
+                Box::new(move |msg, _| {
+                    routes.lock().unwrap().dispatch(msg);
+                    true
+                }),
+            )
+        };
+        Dispatcher {
+            con,
+            peer,
+            token,
+            routes,
+            rules: tokio::sync::Mutex::new(Rules::default()),
+        }
+    }
+
+    fn bus_rule((name, interface, member): &Rule) -> String {
+        format!(
+            "type='signal',sender='{}',interface='{}',member='{}'",
+            name, interface, member
+        )
+    }
+
+    /// Receive the signal `interface.member` sent from `path` by the
+    /// bus name `name`, which is currently owned by `sender`. The last
+    /// field is true if a new bus rule was added.
+    pub async fn subscribe(
+        &self,
+        name: &str,
+        sender: Option<Arc<str>>,
+        path: &str,
+        interface: &str,
+        member: &str,
+    ) -> Result<(Subscription, UnboundedReceiver<Message>, bool)> {
+        let mut added = false;
+        let rule = if self.peer {
+            None
+        } else {
+            let rule = (
+                String::from(name),
+                String::from(interface),
+                String::from(member),
+            );
+            let mut rules = self.rules.lock().await;
+            if rules.add(&rule) {
+                if let Err(e) = self.con.add_match_no_cb(&Self::bus_rule(&rule)).await {
+                    rules.remove(&rule);
+                    return Err(e.into());
+                }
+                added = true;
+            }
+            Some(rule)
+        };
+        let (tx, rx) = mpsc::unbounded();
+        let route = Route {
+            path: String::from(path),
+            interface: String::from(interface),
+            member: String::from(member),
+        };
+        let mut routes = self.routes.lock().unwrap();
+        let id = routes.next;
+        routes.next += 1;
+        routes.by_id.insert(id, route.clone());
+        routes.insert(route, Subscriber { id, sender, tx });
+        Ok((Subscription { id, rule }, rx, added))
+    }
+
+    /// Stop receiving a signal. If `connected` is false the bus rules
+    /// died with the connection, so they aren't removed. Returns true
+    /// if a bus rule was removed.
+    pub async fn unsubscribe(&self, sub: Subscription, connected: bool) -> bool {
+        {
+            let mut routes = self.routes.lock().unwrap();
+            if let Some(route) = routes.by_id.remove(&sub.id) {
+                routes.remove(&route, sub.id)
+            }
+        }
+        let rule = match sub.rule {
+            None => return false,
+            Some(rule) => rule,
+        };
+        let removed = self.rules.lock().await.remove(&rule);
+        if removed && connected {
+            let _: Result<_, _> = self.con.remove_match_no_cb(&Self::bus_rule(&rule)).await;
+        }
+        removed
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.con.stop_receive(self.token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::strings::BusName;
+
+    fn route(path: &str, member: &str) -> Route {
+        Route {
+            path: String::from(path),
+            interface: String::from("org.example.Foo"),
+            member: String::from(member),
+        }
+    }
+
+    fn signal(sender: &str, path: &str, member: &str) -> Message {
+        let mut msg = Message::new_signal(path, "org.example.Foo", member).unwrap();
+        msg.set_sender(Some(BusName::new(sender).unwrap()));
+        msg
+    }
+
+    fn received(rx: &mut UnboundedReceiver<Message>) -> usize {
+        let mut n = 0;
+        while rx.try_recv().is_ok() {
+            n += 1
+        }
+        n
+    }
+
+    #[test]
+    fn rules() {
+        let mut rules = Rules::default();
+        let rule = |m: &str| {
+            let (n, i) = (
+                String::from("org.example.Foo"),
+                String::from("org.example.Foo"),
+            );
+            (n, i, String::from(m))
+        };
+        assert!(rules.add(&rule("Changed")));
+        assert!(!rules.add(&rule("Changed")));
+        assert!(rules.add(&rule("Removed")));
+        assert!(!rules.remove(&rule("Changed")));
+        assert!(rules.remove(&rule("Changed")));
+        assert!(!rules.remove(&rule("Changed")));
+        assert!(rules.add(&rule("Changed")));
+        assert!(rules.remove(&rule("Removed")));
+    }
+
+    #[test]
+    fn routing() {
+        let mut routes = Routes::default();
+        let mut subscribe = |id, sender: Option<&str>, route| {
+            let (tx, rx) = mpsc::unbounded();
+            let sender = sender.map(Arc::from);
+            routes.insert(route, Subscriber { id, sender, tx });
+            rx
+        };
+        let mut a = subscribe(0, Some(":1.1"), route("/a", "Changed"));
+        let mut b = subscribe(1, Some(":1.2"), route("/a", "Changed"));
+        let mut any = subscribe(2, None, route("/a", "Changed"));
+        let mut other = subscribe(3, Some(":1.1"), route("/b", "Removed"));
+        routes.dispatch(signal(":1.1", "/a", "Changed"));
+        assert_eq!(received(&mut a), 1);
+        assert_eq!(received(&mut b), 0);
+        assert_eq!(received(&mut any), 1);
+        assert_eq!(received(&mut other), 0);
+        routes.dispatch(signal(":1.2", "/a", "Changed"));
+        assert_eq!((received(&mut a), received(&mut b)), (0, 1));
+        assert_eq!(received(&mut any), 1);
+        // the wrong path, member, or sender isn't routed
+        routes.dispatch(signal(":1.1", "/b", "Changed"));
+        routes.dispatch(signal(":1.1", "/a", "Removed"));
+        routes.dispatch(signal(":1.2", "/b", "Removed"));
+        assert_eq!(received(&mut a) + received(&mut any), 0);
+        assert_eq!(received(&mut other), 0);
+        routes.dispatch(signal(":1.1", "/b", "Removed"));
+        assert_eq!(received(&mut other), 1);
+        // removing a route leaves the others alone, and cleans up
+        routes.remove(&route("/a", "Changed"), 0);
+        routes.dispatch(signal(":1.1", "/a", "Changed"));
+        assert_eq!((received(&mut a), received(&mut any)), (0, 1));
+        routes.remove(&route("/a", "Changed"), 1);
+        routes.remove(&route("/a", "Changed"), 2);
+        routes.remove(&route("/b", "Removed"), 3);
+        assert!(routes.by_route.is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    prelude::*,
+};
+use fxhash::FxHashMap;
+use netidx::publisher::{Event, Id, Publisher};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+use tokio::task;
+
+type Watchers = Mutex<FxHashMap<Id, UnboundedSender<()>>>;
+
+/// Tells tasks when the values they publish gain their first netidx
+/// subscriber or lose their last one, so work that only matters to
+/// subscribers can be skipped while there are none. The publisher
+/// sends it's subscription events to one channel, and they are routed
+/// from there to the watch of the value they are about.
+#[derive(Clone)]
+pub struct Interest {
+    publisher: Publisher,
+    watchers: Arc<Watchers>,
+}
+
+impl Interest {
+    pub fn new(publisher: Publisher) -> Self {
+        let watchers = Arc::new(Mutex::new(HashMap::default()));
+        let (tx, rx) = mpsc::unbounded();
+        publisher.events(tx);
+        task::spawn(Self::run(rx, Arc::downgrade(&watchers)));
+        Interest {
+            publisher,
+            watchers,
+        }
+    }
+
+    async fn run(mut events: UnboundedReceiver<Event>, watchers: Weak<Watchers>) {
+        while let Some(e) = events.next().await {
+            let watchers = match watchers.upgrade() {
+                Some(watchers) => watchers,
+                None => break,
+            };
+            let id = match e {
+                Event::Subscribe(id, _) | Event::Unsubscribe(id, _) => id,
+                Event::Destroyed(_) => continue,
+            };
+            let tx = watchers.lock().unwrap().get(&id).cloned();
+            if let Some(tx) = tx {
+                let _ = tx.unbounded_send(());
+            }
+        }
+    }
+
+    /// Watch the subscribers of a set of values, which starts empty
+    pub fn watch(&self) -> Watch {
+        let (tx, rx) = mpsc::unbounded();
+        Watch {
+            interest: self.clone(),
+            ids: Vec::new(),
+            tx,
+            rx,
+        }
+    }
+}
+
+/// Watches the subscribers of a set of values, they are forgotten
+/// when it is dropped
+pub struct Watch {
+    interest: Interest,
+    ids: Vec<Id>,
+    tx: UnboundedSender<()>,
+    rx: UnboundedReceiver<()>,
+}
+
+impl Watch {
+    pub fn add(&mut self, id: Id) {
+        self.ids.push(id);
+        self.interest
+            .watchers
+            .lock()
+            .unwrap()
+            .insert(id, self.tx.clone());
+        // it may already be subscribed
+        let _ = self.tx.unbounded_send(());
+    }
+
+    pub fn remove(&mut self, id: &Id) {
+        self.ids.retain(|i| i != id);
+        self.interest.watchers.lock().unwrap().remove(id);
+    }
+
+    /// True if any of the values has a subscriber
+    pub fn subscribed(&self) -> bool {
+        let publisher = &self.interest.publisher;
+        self.ids.iter().any(|id| publisher.subscribed_len(id) > 0)
+    }
+
+    /// Wait until the subscribers of any of the values may have changed
+    pub async fn changed(&mut self) {
+        // it can't end, the watch holds a sender
+        let _ = self.rx.next().await;
+        // a burst of subscriptions is one change
+        while self.rx.try_recv().is_ok() {}
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut watchers = self.interest.watchers.lock().unwrap();
+        for id in &self.ids {
+            watchers.remove(id);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Limits on crawling object trees, so huge or broken services can't
+/// swamp the bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// how many introspection calls may be outstanding, across all buses
+    pub introspections: usize,
+    /// how many levels below the root object of a bus name are published
+    pub depth: usize,
+    /// how many objects are published per bus name
+    pub objects: usize,
+}
+
+/// Why children of an object weren't published
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Truncated {
+    Depth(usize),
+    Objects(usize),
+    /// the object is the same as the ancestor at this path
+    Cycle(String),
+}
+
+impl Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncated::Depth(n) => write!(f, "the maximum depth of {} was reached", n),
+            Truncated::Objects(n) => write!(f, "the maximum of {} objects was reached", n),
+            Truncated::Cycle(path) => write!(f, "they repeat {}", path),
+        }
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// Counts the objects published for one bus name
+#[derive(Debug, Clone)]
+pub struct Budget {
+    used: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl Budget {
+    pub fn new(max: usize) -> Self {
+        Budget {
+            used: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Count one more object until the returned value is dropped, or
+    /// fail if the budget is used up
+    pub fn take(&self) -> Result<Taken, Truncated> {
+        let max = self.max;
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| Truncated::Objects(max))?;
+        Ok(Taken(Arc::clone(&self.used)))
+    }
+}
+
+/// One object counted against a budget
+#[derive(Debug)]
+pub struct Taken(Arc<AtomicUsize>);
+
+impl Drop for Taken {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,6 +1,19 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod access;
+mod alias;
+mod audit;
+mod cache;
+mod config;
+mod dispatch;
+mod interest;
+mod limits;
+mod notify;
+mod redact;
+mod rules;
+mod stats;
+mod unique;
 mod xml;
 use anyhow::{anyhow, bail, Result};
 use dbus::{
@@ -9,19 +22,21 @@ use dbus::{
         messageitem::{MessageItem, MessageItemArray, MessageItemDict},
         AppendAll, ArgType, IterAppend, ReadAll, RefArg,
     },
+    channel::{BusType, Channel},
     message::MatchRule,
     nonblock::{
         stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged},
-        MethodReply, MsgMatch, Proxy, SyncConnection,
+        MethodReply, Proxy, SyncConnection,
     },
     strings, Message,
 };
+use dbus_tokio::connection::{IOResource, IOResourceError};
 use futures::{
     channel::{
         mpsc::{self, UnboundedReceiver},
         oneshot,
     },
-    future,
+    future::{self, BoxFuture},
     prelude::*,
     select_biased,
 };
@@ -38,20 +53,64 @@ use netidx_protocols::rpc::server as rpc;
 use netidx_tools_core::ClientParams;
 use std::{
     boxed::Box,
-    collections::{HashMap, HashSet},
+    cmp,
+    collections::{hash_map::Entry, HashMap, HashSet},
     fmt::Display,
-    iter,
-    pin::Pin,
-    result,
+    io, iter, mem,
+    path::PathBuf,
+    process, result,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
     time::Duration,
 };
 use structopt::StructOpt;
-use tokio::task;
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{watch, Semaphore},
+    task,
+    time::{self, Instant},
+};
+
+// how long to wait for rpcs to be unpublished before republishing
+const UNPUBLISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// the destination of method calls to a peer, which doesn't have a name
+const PEER_NAME: &str = "org.netidx.Peer";
+
+// introspection of a newly claimed name is retried this many times,
+// with backoff starting at INTROSPECT_RETRY_MIN
+const INTROSPECT_TRIES: usize = 6;
+const INTROSPECT_RETRY_MIN: Duration = Duration::from_millis(500);
+
+// newly claimed names are introspected again after SETTLE_MIN, then
+// at doubling intervals up to --settle-max
+const SETTLE_MIN: Duration = Duration::from_secs(1);
+
+// reconnect backoff bounds
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+// how often the config file is checked for changes. This, and
+// STATUS_INTERVAL, only decide how quickly the bridge notices things
+// about itself, so they aren't settings.
+const CONFIG_POLL: Duration = Duration::from_secs(2);
 
-// make this an argument?
-const TIMEOUT: Duration = Duration::from_secs(30);
+// how often systemd is told how the bridge is doing, and how often
+// it is checked until the bridge is ready
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+const READY_POLL: Duration = Duration::from_millis(100);
+
+// how long in flight calls, property sets, and activations are given
+// to finish when shutting down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how long a bridge is given to unpublish everything when it is
+// stopped, after that it's task is aborted
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(StructOpt, Debug)]
 struct Params {
@@ -64,6 +123,23 @@ struct Params {
         default_value = "local"
     )]
     bind: BindCfg,
+    #[structopt(
+        long = "bridge-config",
+        help = "read settings from this TOML or JSON file, and apply changes to it while running"
+    )]
+    bridge_config: Option<PathBuf>,
+    #[structopt(
+        long = "ready-before-crawl",
+        help = "tell systemd the bridge is ready once the buses are connected and cached trees are published, without waiting for the crawl"
+    )]
+    ready_before_crawl: bool,
+    #[structopt(flatten)]
+    bridge: BridgeParams,
+}
+
+/// The settings that can also be given in the config file
+#[derive(StructOpt, Debug, Clone)]
+struct BridgeParams {
     #[structopt(
         long = "timeout",
         help = "require subscribers to consume values before timeout (seconds)"
@@ -77,16 +153,327 @@ struct Params {
     netidx_base: Path,
     #[structopt(
         long = "system",
-        help = "connect to the system bus instead of the session bus"
+        help = "bridge the system bus, same as --address system"
     )]
     system: bool,
+    #[structopt(
+        long = "address",
+        help = "bridge a bus, [subtree=]address where address is session, system, or a dbus address (may be repeated)",
+        number_of_values = 1
+    )]
+    address: Vec<BusSpec>,
+    #[structopt(
+        long = "cache-dir",
+        help = "cache introspection data in this directory to speed up restarts"
+    )]
+    cache_dir: Option<PathBuf>,
+    #[structopt(
+        long = "users",
+        help = "bridge the session bus of every logged in user under users/<name> (run as root)"
+    )]
+    users: bool,
+    #[structopt(
+        long = "peer",
+        help = "bridge a peer to peer connection, [subtree=]address (may be repeated)",
+        number_of_values = 1
+    )]
+    peer: Vec<BusSpec>,
+    #[structopt(
+        long = "unique",
+        help = "also proxy unique connection names under connections/unique"
+    )]
+    unique: bool,
+    #[structopt(
+        long = "unique-filter",
+        help = "only proxy unique names owned by a matching process=<glob> or user=<glob> (may be repeated)",
+        number_of_values = 1
+    )]
+    unique_filter: Vec<unique::Filter>,
+    #[structopt(
+        long = "rule",
+        help = "publish, read-only, no-methods, or hide matching items, e.g. 'hide name=org.freedesktop.secrets' (may be repeated)",
+        number_of_values = 1
+    )]
+    rule: Vec<rules::Rule>,
+    #[structopt(
+        long = "rules-file",
+        help = "read rules from this file, one per line, after the ones given by --rule"
+    )]
+    rules_file: Option<PathBuf>,
+    #[structopt(
+        long = "alias",
+        help = "also publish a property, signal, or method at a path, <path>=<bus name>:<object path>:<interface>.<member> (may be repeated)",
+        number_of_values = 1
+    )]
+    alias: Vec<alias::Alias>,
+    #[structopt(
+        long = "read-only",
+        help = "never accept property writes, method calls, or service activations"
+    )]
+    read_only: bool,
+    #[structopt(
+        long = "access",
+        help = "allow or deny matching calls, sets, and activations, e.g. 'allow group=wheel name=org.freedesktop.login1' (may be repeated)",
+        number_of_values = 1
+    )]
+    access: Vec<access::Rule>,
+    #[structopt(
+        long = "audit-log",
+        help = "log every method call, property set, service activation, and login to this file"
+    )]
+    audit_log: Option<PathBuf>,
+    #[structopt(
+        long = "redact",
+        help = "keep, placeholder, or hash matching values, e.g. 'placeholder interface=org.example.Keys member=Token' (may be repeated)",
+        number_of_values = 1
+    )]
+    redact: Vec<redact::Redaction>,
+    #[structopt(
+        long = "no-default-redactions",
+        help = "don't redact values from well known sensitive interfaces"
+    )]
+    no_default_redactions: bool,
+    #[structopt(
+        long = "own-name",
+        help = "own this bus name, e.g. org.netidx.Bridge, on every bus that is bridged, so the bridge can be dbus activated, and only one instance runs"
+    )]
+    own_name: Option<String>,
+    #[structopt(
+        long = "lazy-properties",
+        help = "only follow the property changes of objects that have a subscriber, values are refreshed when the first one arrives"
+    )]
+    lazy_properties: bool,
+    #[structopt(
+        long = "max-introspections",
+        help = "how many objects may be introspected at once, across all buses",
+        default_value = "32"
+    )]
+    max_introspections: usize,
+    #[structopt(
+        long = "max-depth",
+        help = "don't publish objects more than this many levels below the root object of a bus name",
+        default_value = "64"
+    )]
+    max_depth: usize,
+    #[structopt(
+        long = "max-objects",
+        help = "publish at most this many objects per bus name",
+        default_value = "10000"
+    )]
+    max_objects: usize,
+    #[structopt(
+        long = "call-timeout",
+        help = "how long to wait for a reply to a dbus method call, including introspection (seconds)",
+        default_value = "30"
+    )]
+    call_timeout: u64,
+    #[structopt(
+        long = "settle-max",
+        help = "introspect newly claimed names again at doubling intervals up to this long after they appear (seconds, 0 to not)",
+        default_value = "32"
+    )]
+    settle_max: u64,
+    #[structopt(
+        long = "retained-grace",
+        help = "how long values kept from a lost connection or a previous owner wait for their objects to come back (seconds)",
+        default_value = "30"
+    )]
+    retained_grace: u64,
+}
+
+impl BridgeParams {
+    /// Override the command line with the settings in `cfg`
+    fn merge(&mut self, cfg: config::Config) -> Result<()> {
+        fn parse<T: FromStr<Err = anyhow::Error>>(v: Vec<String>) -> Result<Vec<T>> {
+            v.iter()
+                .map(|s| s.parse::<T>().map_err(|e| anyhow!("{} {}", s, e)))
+                .collect()
+        }
+        if let Some(base) = cfg.netidx_base {
+            self.netidx_base = Path::from(base);
+        }
+        if let Some(timeout) = cfg.timeout {
+            self.timeout = Some(timeout);
+        }
+        if let Some(system) = cfg.system {
+            self.system = system;
+        }
+        if let Some(address) = cfg.address {
+            self.address = parse(address)?;
+        }
+        if let Some(peer) = cfg.peer {
+            self.peer = parse(peer)?;
+        }
+        if let Some(users) = cfg.users {
+            self.users = users;
+        }
+        if let Some(dir) = cfg.cache_dir {
+            self.cache_dir = Some(dir);
+        }
+        if let Some(unique) = cfg.unique {
+            self.unique = unique;
+        }
+        if let Some(filter) = cfg.unique_filter {
+            self.unique_filter = parse(filter)?;
+        }
+        if let Some(rules) = cfg.rules {
+            self.rule = parse(rules)?;
+        }
+        if let Some(file) = cfg.rules_file {
+            self.rules_file = Some(file);
+        }
+        if let Some(aliases) = cfg.aliases {
+            self.alias = parse(aliases)?;
+        }
+        if let Some(read_only) = cfg.read_only {
+            self.read_only = read_only;
+        }
+        if let Some(access) = cfg.access {
+            self.access = parse(access)?;
+        }
+        if let Some(file) = cfg.audit_log {
+            self.audit_log = Some(file);
+        }
+        if let Some(redact) = cfg.redact {
+            self.redact = parse(redact)?;
+        }
+        if let Some(defaults) = cfg.default_redactions {
+            self.no_default_redactions = !defaults;
+        }
+        if let Some(name) = cfg.own_name {
+            self.own_name = Some(name);
+        }
+        if let Some(lazy) = cfg.lazy_properties {
+            self.lazy_properties = lazy;
+        }
+        if let Some(n) = cfg.max_introspections {
+            self.max_introspections = n;
+        }
+        if let Some(n) = cfg.max_depth {
+            self.max_depth = n;
+        }
+        if let Some(n) = cfg.max_objects {
+            self.max_objects = n;
+        }
+        if let Some(secs) = cfg.call_timeout {
+            self.call_timeout = secs;
+        }
+        if let Some(secs) = cfg.settle_max {
+            self.settle_max = secs;
+        }
+        if let Some(secs) = cfg.retained_grace {
+            self.retained_grace = secs;
+        }
+        Ok(())
+    }
+}
+
+/// How long to wait for dbus, and for things to settle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Timing {
+    /// the timeout of dbus method calls
+    call: Duration,
+    /// newly claimed names are introspected again until this long after
+    /// they appear
+    settle: Duration,
+    /// how long retained values wait for their objects to come back
+    retained: Duration,
+}
+
+/// Everything that decides what is bridged and how, from the command
+/// line and the config file. When the config file changes the
+/// running bridges are brought in line with the new settings.
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    base: Path,
+    timeout: Option<Duration>,
+    buses: Vec<(Option<String>, BusAddress)>,
+    users: bool,
+    cache_dir: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    own_name: Option<String>,
+    lazy_properties: bool,
+    limits: limits::Limits,
+    timing: Timing,
+    unique: Option<Arc<Vec<unique::Filter>>>,
+    policy: Policy,
+}
+
+impl Settings {
+    async fn load(opts: &BridgeParams, config: Option<&std::path::Path>) -> Result<Self> {
+        let mut opts = opts.clone();
+        if let Some(file) = config {
+            let cfg = config::Config::load(file)
+                .await
+                .map_err(|e| anyhow!("{} {}", file.display(), e))?;
+            opts.merge(cfg)?;
+        }
+        let buses = BusSpec::resolve(&opts)?;
+        let mut rules = opts.rule;
+        if let Some(file) = &opts.rules_file {
+            rules.extend(rules::Rules::load(file).await?);
+        }
+        Ok(Settings {
+            base: opts.netidx_base,
+            timeout: opts.timeout.map(Duration::from_secs),
+            buses,
+            users: opts.users,
+            cache_dir: opts.cache_dir,
+            audit_log: opts.audit_log,
+            own_name: opts.own_name,
+            lazy_properties: opts.lazy_properties,
+            limits: limits::Limits {
+                introspections: opts.max_introspections,
+                depth: opts.max_depth,
+                objects: opts.max_objects,
+            },
+            timing: Timing {
+                call: Duration::from_secs(opts.call_timeout),
+                settle: Duration::from_secs(opts.settle_max),
+                retained: Duration::from_secs(opts.retained_grace),
+            },
+            unique: if opts.unique || !opts.unique_filter.is_empty() {
+                Some(Arc::new(opts.unique_filter))
+            } else {
+                None
+            },
+            policy: Policy {
+                rules: rules::Rules::new(rules),
+                aliases: alias::Aliases::new(opts.alias),
+                read_only: opts.read_only,
+                access: access::Access::new(opts.access),
+                redact: redact::Redactions::new(opts.redact, !opts.no_default_redactions),
+            },
+        })
+    }
 }
 
 async fn introspect(con: &Proxy<'_, Arc<SyncConnection>>) -> Result<xml::Node> {
     let (xml,): (String,) = con
         .method_call("org.freedesktop.DBus.Introspectable", "Introspect", ())
         .await?;
-    Ok(xml::Node::from_reader(xml.as_bytes())?)
+    xml.parse()
+}
+
+async fn connection_unix_process_id(
+    con: &Proxy<'_, Arc<SyncConnection>>,
+    name: &str,
+) -> Result<u32> {
+    let (pid,): (u32,) = con
+        .method_call(
+            "org.freedesktop.DBus",
+            "GetConnectionUnixProcessID",
+            (name,),
+        )
+        .await?;
+    Ok(pid)
+}
+
+async fn get_name_owner(con: &Proxy<'_, Arc<SyncConnection>>, name: &str) -> Result<String> {
+    let (owner,): (String,) = con
+        .method_call("org.freedesktop.DBus", "GetNameOwner", (name,))
+        .await?;
+    Ok(owner)
 }
 
 async fn list_names(con: &Proxy<'_, Arc<SyncConnection>>) -> Result<Vec<String>> {
@@ -103,6 +490,507 @@ async fn list_activatable_names(con: &Proxy<'_, Arc<SyncConnection>>) -> Result<
     Ok(names)
 }
 
+/// The bus to connect to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum BusAddress {
+    Session,
+    System,
+    Address(String),
+    /// The session bus of a user, connected to with the user's credentials
+    User {
+        uid: u32,
+        gid: u32,
+    },
+    /// A peer to peer connection, without a bus daemon
+    Peer(String),
+}
+
+impl Display for BusAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusAddress::Session => write!(f, "the session bus"),
+            BusAddress::System => write!(f, "the system bus"),
+            BusAddress::Address(a) => write!(f, "{}", a),
+            BusAddress::User { uid, .. } => write!(f, "the session bus of uid {}", uid),
+            BusAddress::Peer(a) => write!(f, "the peer {}", a),
+        }
+    }
+}
+
+impl BusAddress {
+    /// Open a channel to the bus and register with it. This blocks
+    /// until it is done.
+    fn open(&self) -> Result<Channel> {
+        Ok(match self {
+            BusAddress::Session => Channel::get_private(BusType::Session)?,
+            BusAddress::System => Channel::get_private(BusType::System)?,
+            BusAddress::Address(a) => {
+                let mut channel = Channel::open_private(a)?;
+                channel.register()?;
+                channel
+            }
+            BusAddress::User { uid, gid } => {
+                // acting as the user is done on a thread of it's own,
+                // which exits afterwards, rather than on a thread of
+                // the blocking pool that would keep the credentials
+                let (uid, gid) = (*uid, *gid);
+                thread::spawn(move || {
+                    as_user(uid, gid)?;
+                    let mut channel =
+                        Channel::open_private(&format!("unix:path=/run/user/{}/bus", uid))?;
+                    channel.register()?;
+                    Ok::<_, anyhow::Error>(channel)
+                })
+                .join()
+                .map_err(|_| anyhow!("connecting to the session bus of uid {} panicked", uid))??
+            }
+            // there is no bus to register with
+            BusAddress::Peer(a) => Channel::open_private(a)?,
+        })
+    }
+
+    async fn connect(&self) -> Result<(IOResource<SyncConnection>, Arc<SyncConnection>)> {
+        let bus = self.clone();
+        let channel = task::spawn_blocking(move || bus.open()).await??;
+        Ok(dbus_tokio::connection::from_channel(channel)?)
+    }
+}
+
+/// Switch the calling thread to the effective uid and gid of a
+/// user, with no supplementary groups, so root's groups aren't
+/// carried over. The session bus only lets it's own user connect, and
+/// it checks the credentials of the socket when it is connected and
+/// authenticated. Only call this on a thread that exits once it is
+/// done acting as the user.
+fn as_user(uid: u32, gid: u32) -> Result<()> {
+    // the libc wrappers change the credentials of every thread in
+    // the process, the raw syscalls only change the calling thread.
+    // The groups go first, changing them needs root.
+    let groups: [libc::gid_t; 1] = [gid];
+    if unsafe { libc::syscall(libc::SYS_setgroups, 1, groups.as_ptr()) } != 0 {
+        bail!(
+            "failed to set groups {} {}",
+            gid,
+            io::Error::last_os_error()
+        )
+    }
+    let keep = libc::uid_t::MAX;
+    if unsafe { libc::syscall(libc::SYS_setresgid, keep, gid, keep) } != 0 {
+        bail!("failed to set gid {} {}", gid, io::Error::last_os_error())
+    }
+    if unsafe { libc::syscall(libc::SYS_setresuid, keep, uid, keep) } != 0 {
+        bail!("failed to set uid {} {}", uid, io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// A bus to bridge, and the subtree of --netidx-base to publish it
+/// under, written as `[subtree=]address`
+#[derive(Debug, Clone)]
+struct BusSpec {
+    subtree: Option<String>,
+    address: BusAddress,
+}
+
+impl FromStr for BusSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // dbus addresses contain '=' too, but a subtree can't contain ':'
+        let (subtree, address) = match s.split_once('=') {
+            Some((subtree, address)) if !subtree.contains(':') => {
+                if subtree.is_empty() || subtree.contains('/') {
+                    bail!("invalid subtree {}", subtree)
+                }
+                (Some(String::from(subtree)), address)
+            }
+            Some(_) | None => (None, s),
+        };
+        let address = match address {
+            "session" => BusAddress::Session,
+            "system" => BusAddress::System,
+            a if a.contains(':') => BusAddress::Address(String::from(a)),
+            a => bail!("invalid bus address {}", a),
+        };
+        Ok(BusSpec { subtree, address })
+    }
+}
+
+impl BusSpec {
+    /// Work out the buses to bridge and where to publish them. With a
+    /// single bus the subtree defaults to --netidx-base itself, so
+    /// existing setups keep their paths. With more than one bus the
+    /// session and system buses default to `session` and `system`,
+    /// and other addresses must name their subtree. With --users the
+    /// session bus is only bridged if it is asked for.
+    fn resolve(opts: &BridgeParams) -> Result<Vec<(Option<String>, BusAddress)>> {
+        let mut specs = opts.address.clone();
+        if opts.system {
+            specs.push(BusSpec {
+                subtree: None,
+                address: BusAddress::System,
+            })
+        }
+        for spec in &opts.peer {
+            match &spec.address {
+                BusAddress::Address(a) => specs.push(BusSpec {
+                    subtree: spec.subtree.clone(),
+                    address: BusAddress::Peer(a.clone()),
+                }),
+                bus => bail!("{} is a bus, a peer needs a dbus address", bus),
+            }
+        }
+        if specs.is_empty() && !opts.users {
+            specs.push(BusSpec {
+                subtree: None,
+                address: BusAddress::Session,
+            })
+        }
+        let single = specs.len() == 1 && !opts.users;
+        let mut subtrees = HashSet::new();
+        let mut buses = Vec::new();
+        for spec in specs {
+            let subtree = match (spec.subtree, &spec.address) {
+                (Some(s), _) => Some(s),
+                (None, _) if single => None,
+                (None, BusAddress::Session) => Some(String::from("session")),
+                (None, BusAddress::System) => Some(String::from("system")),
+                (None, bus) => {
+                    bail!("a subtree is required for {}, e.g. name={}", bus, bus)
+                }
+            };
+            if opts.users && subtree.as_deref() == Some("users") {
+                bail!("the users subtree is used by --users")
+            }
+            if !subtrees.insert(subtree.clone()) {
+                bail!("more than one bus is published under {:?}", subtree)
+            }
+            buses.push((subtree, spec.address));
+        }
+        Ok(buses)
+    }
+}
+
+/// Values that stay published, as errors, while their bus is
+/// disconnected. When the bus comes back they are reused, so
+/// subscribers stay subscribed and just see the new value.
+#[derive(Default)]
+struct Retained {
+    disconnected: AtomicBool,
+    vals: Mutex<FxHashMap<Path, Val>>,
+}
+
+/// Shared state needed to publish objects
+#[derive(Clone)]
+struct Ctx {
+    timeout: Option<Duration>,
+    publisher: Publisher,
+    cache: Option<Arc<cache::Cache>>,
+    retained: Arc<Retained>,
+    retain: Arc<AtomicBool>,
+    peer: bool,
+    unique: Option<Arc<Vec<unique::Filter>>>,
+    refresh: Option<mpsc::Sender<Pooled<Vec<WriteRequest>>>>,
+    policy: watch::Receiver<Arc<Policy>>,
+    audit: audit::Audit,
+    logins: access::Logins,
+    stats: stats::Stats,
+    closing: Arc<AtomicBool>,
+    own_name: Option<Arc<str>>,
+    dispatch: Option<Arc<dispatch::Dispatcher>>,
+    interest: interest::Interest,
+    lazy_properties: bool,
+    limits: limits::Limits,
+    timing: Timing,
+    introspecting: Arc<Semaphore>,
+    // the objects published for a bus name
+    budget: limits::Budget,
+    // the unique name of the owner of the bus name being published
+    owner: Option<Arc<str>>,
+}
+
+type Match = (dispatch::Subscription, UnboundedReceiver<Message>);
+
+/// The next message received by `filter`, never if there isn't one
+async fn next_match(filter: &mut Option<Match>) -> Message {
+    match filter {
+        Some((_, rx)) => match rx.next().await {
+            Some(msg) => msg,
+            None => future::pending().await,
+        },
+        None => future::pending().await,
+    }
+}
+
+/// The settings that decide what is published and where. They can
+/// change while running, and the published tree follows them.
+#[derive(Debug, Clone, PartialEq)]
+struct Policy {
+    rules: rules::Rules,
+    aliases: alias::Aliases,
+    read_only: bool,
+    access: access::Access,
+    redact: redact::Redactions,
+}
+
+impl Policy {
+    /// What to do with an item, see `rules::Rules::check`. In read
+    /// only mode everything that is published is read only.
+    fn check(
+        &self,
+        name: &str,
+        path: Option<&str>,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> rules::Action {
+        match self.rules.check(name, path, interface, member) {
+            rules::Action::Hide => rules::Action::Hide,
+            _ if self.read_only => rules::Action::ReadOnly,
+            action => action,
+        }
+    }
+
+    /// True if anything published for the interfaces of `node`, the
+    /// object `path` of bus name `name`, would be different under
+    /// `old`, because an action, an alias, or a redaction changed.
+    fn differs(&self, old: &Policy, name: &str, path: &str, node: &xml::Node) -> bool {
+        node.interfaces().into_iter().any(|i| {
+            let action = |p: &Policy, m: Option<&str>| p.check(name, Some(path), Some(&i.name), m);
+            let aliases = |p: &Policy, m: &str| {
+                let paths = p.aliases.find(name, path, &i.name, m);
+                paths.cloned().collect::<Vec<Path>>()
+            };
+            let mut members = Vec::new();
+            for m in i.methods() {
+                members.push((m.name.as_str(), m.args()))
+            }
+            for s in i.signals() {
+                members.push((s.name.as_str(), s.args()))
+            }
+            for p in i.properties() {
+                members.push((p.name.as_str(), Vec::new()))
+            }
+            action(self, None) != action(old, None)
+                || members.into_iter().any(|(m, args)| {
+                    let redacted = iter::once(None)
+                        .chain(args.into_iter().map(|a| a.name.as_deref()))
+                        .any(|a| {
+                            self.redact.check(&i.name, m, a) != old.redact.check(&i.name, m, a)
+                        });
+                    redacted
+                        || action(self, Some(m)) != action(old, Some(m))
+                        || aliases(self, m) != aliases(old, m)
+                })
+        })
+    }
+}
+
+/// Run `f` unless `stop` fires first, true if it did. `stop` is
+/// terminated after it fires, so the caller must stop too.
+async fn or_stop(
+    mut stop: &mut future::Fuse<oneshot::Receiver<()>>,
+    f: impl Future<Output = ()>,
+) -> bool {
+    let f = f.fuse();
+    futures::pin_mut!(f);
+    select_biased! {
+        _ = stop => true,
+        () = f => false,
+    }
+}
+
+/// Wait until the policy changes. If it can't change anymore this
+/// never returns.
+async fn policy_changed(policy: &mut watch::Receiver<Arc<Policy>>) {
+    if policy.changed().await.is_err() {
+        future::pending::<()>().await
+    }
+}
+
+impl Ctx {
+    fn policy(&self) -> Arc<Policy> {
+        Arc::clone(&self.policy.borrow())
+    }
+
+    /// Alias `id`, the value of `member` of `interface` on the object
+    /// `proxy` points to, at the paths the policy says. A value that
+    /// was retained keeps it's aliases, so only the differences are
+    /// applied.
+    fn publish_aliases(
+        &self,
+        id: Id,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        interface: &str,
+        member: &str,
+    ) {
+        let policy = self.policy();
+        let wanted = policy
+            .aliases
+            .find(&proxy.destination, &proxy.path, interface, member)
+            .collect::<FxHashSet<_>>();
+        let current = self.publisher.aliases(id);
+        for path in &current {
+            if !wanted.contains(path) {
+                self.publisher.remove_alias(id, path)
+            }
+        }
+        for path in wanted {
+            if !current.contains(path) {
+                if let Err(e) = self.publisher.alias(id, path.clone()) {
+                    warn!("failed to publish alias {} {}", path, e)
+                }
+            }
+        }
+    }
+
+    fn disconnected(&self) -> bool {
+        self.retained.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// True if the bridge is shutting down, and no longer accepts
+    /// writes and calls
+    fn closing(&self) -> bool {
+        self.closing.load(Ordering::Relaxed)
+    }
+
+    fn set_disconnected(&self, disconnected: bool) {
+        self.retained
+            .disconnected
+            .store(disconnected, Ordering::Relaxed)
+    }
+
+    /// Publish `path`, reusing the value retained from the last
+    /// connection if there is one.
+    fn publish(&self, path: Path, init: Value) -> Result<Val> {
+        let retained = self.retained.vals.lock().unwrap().remove(&path);
+        match retained {
+            None => Ok(self.publisher.publish(path, init)?),
+            Some(val) => {
+                // the new publisher decides if it is writable
+                self.publisher.stop_writes(val.id());
+                let mut batch = self.publisher.start_batch();
+                val.update(&mut batch, init);
+                task::spawn(batch.commit(self.timeout));
+                Ok(val)
+            }
+        }
+    }
+
+    /// Unpublish `vals`, unless the bus is disconnected, in which case
+    /// they stay published as errors until the bus comes back, or
+    /// they are being handed to the next owner of a name, in which
+    /// case they stay published as they are.
+    fn retire(&self, vals: impl IntoIterator<Item = Val>) {
+        let disconnected = self.disconnected();
+        if !disconnected && !self.retain.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut batch = self.publisher.start_batch();
+        let mut retained = self.retained.vals.lock().unwrap();
+        for val in vals {
+            if let Some(path) = self.publisher.path(val.id()) {
+                self.publisher.stop_writes(val.id());
+                if disconnected {
+                    val.update(
+                        &mut batch,
+                        Value::Error(Chars::from("disconnected from dbus")),
+                    );
+                }
+                retained.insert(path, val);
+            }
+        }
+        task::spawn(batch.commit(self.timeout));
+    }
+
+    /// Start receiving the signal `interface.member` from the object
+    /// `proxy` points to, see `dispatch::Dispatcher`
+    async fn add_match(
+        &self,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        interface: &str,
+        member: &str,
+    ) -> Result<(dispatch::Subscription, UnboundedReceiver<Message>)> {
+        let dispatch = self
+            .dispatch
+            .as_ref()
+            .ok_or_else(|| anyhow!("not connected"))?;
+        let (sub, rx, added) = dispatch
+            .subscribe(
+                &proxy.destination,
+                self.owner.clone(),
+                &proxy.path,
+                interface,
+                member,
+            )
+            .await?;
+        if added {
+            self.stats.add(stats::Gauge::MatchRules, 1);
+        }
+        Ok((sub, rx))
+    }
+
+    /// Add or remove `filter` so it exists only if it is `wanted`.
+    /// Returns true if it was just added.
+    async fn sync_match(
+        &self,
+        filter: &mut Option<Match>,
+        wanted: bool,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        interface: &str,
+        member: &str,
+    ) -> Result<bool> {
+        match filter.take() {
+            None if wanted => {
+                *filter = Some(self.add_match(proxy, interface, member).await?);
+                return Ok(true);
+            }
+            Some((sub, _)) if !wanted => self.remove_match(sub).await,
+            f => *filter = f,
+        }
+        Ok(false)
+    }
+
+    async fn remove_match(&self, sub: dispatch::Subscription) {
+        if let Some(dispatch) = &self.dispatch {
+            // if the connection is gone the match died with it
+            if dispatch.unsubscribe(sub, !self.disconnected()).await {
+                self.stats.add(stats::Gauge::MatchRules, -1);
+            }
+        }
+    }
+
+    /// Introspect the object `proxy` points to, counting failures
+    async fn introspect(&self, proxy: &Proxy<'_, Arc<SyncConnection>>) -> Result<xml::Node> {
+        // the semaphore is never closed
+        let _permit = self.introspecting.acquire().await;
+        let r = introspect(proxy).await;
+        if let Err(e) = &r {
+            self.stats.introspect_failed(e)
+        }
+        r
+    }
+
+    /// Unpublish retained values that weren't reused after reconnecting
+    fn forget_retained(&self) {
+        let vals = mem::take(&mut *self.retained.vals.lock().unwrap());
+        if !vals.is_empty() {
+            info!(
+                "removing {} values that are gone after reconnecting",
+                vals.len()
+            )
+        }
+    }
+
+    /// Unpublish retained values under `base` that weren't reused
+    fn forget_retained_under(&self, base: &Path) {
+        self.retained
+            .vals
+            .lock()
+            .unwrap()
+            .retain(|path, _| !Path::is_parent(base, path))
+    }
+}
+
 #[derive(Debug, Clone)]
 struct NameOwnerChanged {
     name: String,
@@ -297,6 +1185,7 @@ fn netidx_value_to_dbus_value(v: &Value, typ: &DbusType) -> Result<MessageItem>
     }
 }
 
+#[derive(Clone)]
 enum DbusType {
     Byte,
     Bool,
@@ -343,8 +1232,8 @@ impl Display for DbusType {
             Self::String => write!(f, "s"),
             Self::ObjectPath => write!(f, "o"),
             Self::Signature => write!(f, "g"),
-            Self::Array(inner) => write!(f, "a{}", &*inner),
-            Self::Dict { key, value } => write!(f, "{{{}{}}}", &*key, &*value),
+            Self::Array(inner) => write!(f, "a{}", inner),
+            Self::Dict { key, value } => write!(f, "{{{}{}}}", key, value),
             Self::Struct(inner) => {
                 write!(f, "(")?;
                 for t in inner {
@@ -378,7 +1267,7 @@ impl DbusType {
             [b'h', ..] => Ok(Self::UnixFd),
             [b'a', tail @ ..] => Ok(Self::Array(Box::new(Self::from_bytes(tail)?))),
             [b'{', s @ .., b'}'] => {
-                if s.len() == 0 {
+                if s.is_empty() {
                     bail!("empty dict")
                 }
                 let mut s = s;
@@ -392,7 +1281,7 @@ impl DbusType {
                 Ok(Self::Dict { key, value })
             }
             [b'(', s @ .., b')'] => {
-                if s.len() == 0 {
+                if s.is_empty() {
                     bail!("empty struct type")
                 }
                 let mut elts = Vec::new();
@@ -424,7 +1313,7 @@ impl DbusType {
             | Self::Signature
             | Self::UnixFd
             | Self::Variant => 1,
-            Self::Array(elt) => 1 + Self::len(&elt),
+            Self::Array(elt) => 1 + Self::len(elt),
             Self::Struct(elts) => 2 + elts.iter().map(Self::len).sum::<usize>(),
             Self::Dict { key, value } => 2 + Self::len(key) + Self::len(value),
         }
@@ -441,6 +1330,7 @@ struct DbusMethodArgSpec {
     name: Option<String>,
     typ: DbusType,
     direction: DbusArgDirection,
+    doc: Option<String>,
 }
 
 impl<'a> TryFrom<&'a xml::Arg> for DbusMethodArgSpec {
@@ -456,6 +1346,7 @@ impl<'a> TryFrom<&'a xml::Arg> for DbusMethodArgSpec {
                 Some(ref t) if t == "out" => DbusArgDirection::Out,
                 Some(d) => bail!("invalid arg direction {}", d),
             },
+            doc: value.doc.clone(),
         })
     }
 }
@@ -471,8 +1362,8 @@ impl AppendAll for DbusMethodArgs {
 }
 
 impl DbusMethodArgs {
-    fn new<'a>(
-        sig: &Vec<DbusMethodArgSpec>,
+    fn new(
+        sig: &[DbusMethodArgSpec],
         vals: &mut HashMap<Arc<str>, Pooled<Vec<Value>>>,
     ) -> Result<Self> {
         let elts = sig
@@ -511,7 +1402,7 @@ impl ReadAll for DbusMethodRet {
                 }
             }
         }
-        if elts.len() == 0 {
+        if elts.is_empty() {
             Ok(Self(Value::Null))
         } else if elts.len() == 1 {
             Ok(Self(elts.pop().unwrap()))
@@ -521,12 +1412,59 @@ impl ReadAll for DbusMethodRet {
     }
 }
 
-struct ProxiedMethod(rpc::Proc);
+/// Format rpc arguments for the audit log, redacting them as the
+/// arguments of `interface.method`
+fn audit_args(
+    args: &HashMap<Arc<str>, Pooled<Vec<Value>>>,
+    redact: &redact::Redactions,
+    interface: &str,
+    method: &str,
+) -> String {
+    let mut args = args
+        .iter()
+        .map(|(name, vals)| {
+            let mode = redact.check(interface, method, Some(name));
+            match &vals[..] {
+                [v] => format!("{}={}", name, mode.apply(v.clone())),
+                vals => {
+                    let vals = vals
+                        .iter()
+                        .map(|v| mode.apply(v.clone()).to_string())
+                        .collect::<Vec<_>>();
+                    format!("{}=[{}]", name, vals.join(", "))
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    args.sort();
+    args.join(", ")
+}
+
+struct ProxiedMethod {
+    _proc: rpc::Proc,
+    paths: Vec<Path>,
+    _held: stats::Held,
+}
 
 impl ProxiedMethod {
+    /// Publish the rpc at `path` as well
+    fn alias(&self, ctx: &Ctx, path: &Path) -> Result<()> {
+        let base = &self.paths[0];
+        for p in &self.paths {
+            let id = ctx
+                .publisher
+                .id(p)
+                .ok_or_else(|| anyhow!("{} isn't published", p))?;
+            let to = Path::from(format!("{}{}", path, &p[base.len()..]));
+            ctx.publisher.alias(id, to)?
+        }
+        Ok(())
+    }
+
+    /// Publish `method` as an rpc at `base`
     fn new(
         base: Path,
-        publisher: &Publisher,
+        ctx: &Ctx,
         proxy: Proxy<'static, Arc<SyncConnection>>,
         interface: String,
         method: xml::Method,
@@ -570,14 +1508,31 @@ impl ProxiedMethod {
             interface: String,
             method: String,
             proxy: Proxy<'static, Arc<SyncConnection>>,
+            policy: watch::Receiver<Arc<Policy>>,
+            audit: audit::Audit,
+            logins: access::Logins,
+            stats: stats::Stats,
+            closing: Arc<AtomicBool>,
         }
-        let base = base.append(&method.name);
+        let paths = iter::once(base.clone())
+            .chain(iter::once(base.append("doc")))
+            .chain(arg_spec.iter().flat_map(|a| {
+                let base = base.append(a.name.as_ref().unwrap());
+                [base.append("val"), base.append("doc")]
+            }))
+            .collect::<Vec<_>>();
+        let doc = method.doc;
         let spec = Arc::new(Spec {
             arg_spec,
             ret_spec,
             interface,
             method: method.name,
             proxy,
+            policy: ctx.policy.clone(),
+            audit: ctx.audit.clone(),
+            logins: ctx.logins.clone(),
+            stats: ctx.stats.clone(),
+            closing: ctx.closing.clone(),
         });
         let desc = {
             use std::fmt::Write;
@@ -590,143 +1545,380 @@ impl ProxiedMethod {
                 }
                 let _ = write!(desc, "{}", a.typ);
             }
+            if let Some(doc) = doc {
+                let _ = write!(desc, "\n{}", doc);
+            }
             desc
         };
-        let proc = rpc::Proc::new(
-            publisher,
+        let _proc = rpc::Proc::new(
+            &ctx.publisher,
             base,
             Value::from(desc),
             spec.arg_spec
                 .iter()
                 .map(|a| {
                     let name = Arc::from(a.name.as_ref().unwrap().as_str());
-                    let spec = (Value::Null, Value::from(a.typ.to_string()));
+                    let doc = match &a.doc {
+                        None => a.typ.to_string(),
+                        Some(doc) => format!("{}: {}", a.typ, doc),
+                    };
+                    let spec = (Value::Null, Value::from(doc));
                     (name, spec)
                 })
                 .collect(),
-            Arc::new(move |_clid, mut args| {
+            Arc::new(move |clid, mut args| {
                 let spec = Arc::clone(&spec);
                 Box::pin(async move {
-                    match DbusMethodArgs::new(&spec.arg_spec, &mut *args) {
-                        Err(e) => Value::Error(Chars::from(format!(
-                            "failed to construct dbus args: {}",
-                            e
-                        ))),
-                        Ok(dargs) => {
-                            if !args.is_empty() {
-                                warn!("ignoring extra args in method call")
-                            }
-                            let r: MethodReply<DbusMethodRet> =
-                                spec.proxy.method_call(&spec.interface, &spec.method, dargs);
-                            match r.await {
-                                Err(e) => {
-                                    Value::Error(Chars::from(format!("method call failed: {}", e)))
+                    let start = Instant::now();
+                    let _pending = spec.stats.hold(stats::Gauge::PendingWrites);
+                    let policy = Arc::clone(&spec.policy.borrow());
+                    let (i, m) = (&spec.interface, &spec.method);
+                    let (name, path) = (&spec.proxy.destination, &spec.proxy.path);
+                    let printed = audit_args(&args, &policy.redact, i, m);
+                    let target = format!("{}:{} {}.{}", name, path, i, m);
+                    let who = spec.logins.who(clid);
+                    let r = async {
+                        if spec.closing.load(Ordering::Relaxed) {
+                            return Value::Error(Chars::from("shutting down"));
+                        }
+                        // the method may have been made read only since it was published
+                        let allowed = spec
+                            .policy
+                            .borrow()
+                            .check(
+                                &spec.proxy.destination,
+                                Some(&spec.proxy.path),
+                                Some(&spec.interface),
+                                Some(&spec.method),
+                            )
+                            .methods();
+                        if !allowed {
+                            return Value::Error(Chars::from("read only"));
+                        }
+                        let (op, p) = (audit::Op::Call, who.principals.as_slice());
+                        if let Err(e) =
+                            policy
+                                .access
+                                .check(p, op, name, Some(path), Some(i), Some(m))
+                        {
+                            warn!("{} calling {} {}", who, target, e);
+                            return Value::Error(Chars::from(e));
+                        }
+                        match DbusMethodArgs::new(&spec.arg_spec, &mut args) {
+                            Err(e) => Value::Error(Chars::from(format!(
+                                "failed to construct dbus args: {}",
+                                e
+                            ))),
+                            Ok(dargs) => {
+                                if !args.is_empty() {
+                                    warn!("ignoring extra args in method call")
+                                }
+                                let r: MethodReply<DbusMethodRet> =
+                                    spec.proxy.method_call(&spec.interface, &spec.method, dargs);
+                                match r.await {
+                                    Err(e) => Value::Error(Chars::from(format!(
+                                        "method call failed: {}",
+                                        e
+                                    ))),
+                                    Ok(r) => r.0,
                                 }
-                                Ok(r) => r.0,
                             }
                         }
                     }
+                    .await;
+                    let failed = matches!(r, Value::Error(_));
+                    spec.stats.call(&spec.interface, start, failed);
+                    let r = match r {
+                        r @ Value::Error(_) => r,
+                        r => spec
+                            .ret_spec
+                            .iter()
+                            .map(|a| policy.redact.check(i, m, a.name.as_deref()))
+                            .find(|m| m.redacted())
+                            .unwrap_or(redact::Mode::Keep)
+                            .apply(r),
+                    };
+                    let result = match &r {
+                        Value::Error(e) => Err(e.to_string()),
+                        v => Ok(v.to_string()),
+                    };
+                    spec.audit
+                        .record(who, audit::Op::Call, target, printed, start, result);
+                    r
                 })
             }),
         )?;
-        Ok(Self(proc))
+        Ok(Self {
+            _proc,
+            paths,
+            _held: ctx.stats.hold(stats::Gauge::Rpcs),
+        })
     }
 }
 
-struct Object {
-    _methods: Vec<ProxiedMethod>,
-    _children: Vec<Object>,
+/// Errors published where something failed to be published, so
+/// subscribers can see why it is missing without reading the logs.
+/// More than one failure at the same path are joined together.
+#[derive(Clone, Default)]
+struct Errors(Arc<Mutex<FxHashMap<Path, Failure>>>);
+
+struct Failure {
+    val: Val,
+    reasons: Vec<String>,
 }
 
-impl Object {
+impl Errors {
+    fn report(&self, ctx: &Ctx, path: Path, reason: String) {
+        let mut errors = self.0.lock().unwrap();
+        match errors.get_mut(&path) {
+            Some(Failure { val, reasons }) => {
+                reasons.push(reason);
+                let mut batch = ctx.publisher.start_batch();
+                val.update(&mut batch, Value::Error(Chars::from(reasons.join("; "))));
+                task::spawn(batch.commit(ctx.timeout));
+            }
+            None => match ctx.publish(path.clone(), Value::Error(Chars::from(reason.clone()))) {
+                Ok(val) => {
+                    errors.insert(
+                        path,
+                        Failure {
+                            val,
+                            reasons: vec![reason],
+                        },
+                    );
+                }
+                Err(e) => warn!("failed to publish error at {} {}", path, e),
+            },
+        }
+    }
+
+    /// Publish `reason` at `path`, replacing any reasons already there
+    fn replace(&self, ctx: &Ctx, path: Path, reason: String) {
+        {
+            let mut errors = self.0.lock().unwrap();
+            if let Some(Failure { val, reasons }) = errors.get_mut(&path) {
+                let mut batch = ctx.publisher.start_batch();
+                val.update_changed(&mut batch, Value::Error(Chars::from(reason.clone())));
+                task::spawn(batch.commit(ctx.timeout));
+                *reasons = vec![reason];
+                return;
+            }
+        }
+        self.report(ctx, path, reason)
+    }
+
+    /// Unpublish the error at `path` if there is one
+    fn clear(&self, ctx: &Ctx, path: &Path) {
+        let error = self.0.lock().unwrap().remove(path);
+        ctx.retire(error.map(|f| f.val))
+    }
+
+    fn shutdown(&self, ctx: &Ctx) {
+        let errors = mem::take(&mut *self.0.lock().unwrap());
+        ctx.retire(errors.into_values().map(|f| f.val))
+    }
+}
+
+/// A published property
+struct PublishedProperty {
+    val: Val,
+    _held: stats::Held,
+}
+
+/// Everything published for the interfaces of an object. Properties
+/// and signals are published by tasks that run until stop is dropped.
+struct Interfaces {
+    methods: Vec<ProxiedMethod>,
+    docs: Vec<Val>,
+    errors: Errors,
+    tasks: Vec<task::JoinHandle<()>>,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl Interfaces {
+    /// Publish `reason` at the status of `interface`
+    fn failed(ctx: &Ctx, errors: &Errors, base: &Path, interface: &str, reason: String) {
+        warn!("{}", reason);
+        let path = base.append("interfaces").append(interface).append("status");
+        errors.report(ctx, path, reason)
+    }
+
+    /// What the rules say to do with `interface`, or one of it's
+    /// members, on the object `proxy` points to
+    fn check(
+        ctx: &Ctx,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        interface: &str,
+        member: Option<&str>,
+    ) -> rules::Action {
+        ctx.policy().check(
+            &proxy.destination,
+            Some(&proxy.path),
+            Some(interface),
+            member,
+        )
+    }
+
+    fn publish_docs(
+        ctx: &Ctx,
+        base: &Path,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        node: &xml::Node,
+    ) -> Vec<Val> {
+        let mut docs = Vec::new();
+        let mut publish = |path: Path, doc: &Option<String>| {
+            if let Some(doc) = doc {
+                match ctx.publish(path.append("doc"), Value::from(doc.clone())) {
+                    Ok(val) => docs.push(val),
+                    Err(e) => warn!("failed to publish doc for {} {}", path, e),
+                }
+            }
+        };
+        let visible = |i: &str, m: &str| !Self::check(ctx, proxy, i, Some(m)).hidden();
+        for i in node.interfaces() {
+            if Self::check(ctx, proxy, &i.name, None).hidden() {
+                continue;
+            }
+            let base = base.append("interfaces").append(&i.name);
+            publish(base.clone(), &i.doc);
+            for p in i.properties() {
+                if visible(&i.name, &p.name) {
+                    publish(base.append("properties").append(&p.name), &p.doc)
+                }
+            }
+            for s in i.signals() {
+                if visible(&i.name, &s.name) {
+                    publish(base.append("signals").append(&s.name), &s.doc)
+                }
+            }
+        }
+        docs
+    }
+
     fn publish_methods(
+        ctx: &Ctx,
         base: &Path,
-        publisher: &Publisher,
         proxy: &Proxy<'static, Arc<SyncConnection>>,
         node: &xml::Node,
+        errors: &Errors,
     ) -> Vec<ProxiedMethod> {
         node.interfaces()
             .into_iter()
+            .filter(|i| Self::check(ctx, proxy, &i.name, None).methods())
             .flat_map(|i| {
-                i.methods().into_iter().filter_map(|m| {
-                    let base = base.append("interfaces").append(&i.name).append("methods");
-                    match ProxiedMethod::new(
-                        base.clone(),
-                        publisher,
+                i.methods().into_iter().flat_map(|m| {
+                    if !Self::check(ctx, proxy, &i.name, Some(&m.name)).methods() {
+                        return vec![];
+                    }
+                    let mbase = base
+                        .append("interfaces")
+                        .append(&i.name)
+                        .append("methods")
+                        .append(&m.name);
+                    let method = match ProxiedMethod::new(
+                        mbase.clone(),
+                        ctx,
                         proxy.clone(),
                         i.name.clone(),
                         m.clone(),
                     ) {
-                        Ok(p) => Some(p),
+                        Ok(method) => method,
                         Err(e) => {
-                            warn!("failed to proxy method {} {}", base, e);
-                            None
+                            let m = format!("failed to proxy method {} {}", mbase, e);
+                            Self::failed(ctx, errors, base, &i.name, m);
+                            return vec![];
+                        }
+                    };
+                    let policy = ctx.policy();
+                    let aliases =
+                        policy
+                            .aliases
+                            .find(&proxy.destination, &proxy.path, &i.name, &m.name);
+                    for path in aliases {
+                        if let Err(e) = method.alias(ctx, path) {
+                            warn!("failed to publish alias {} {}", path, e)
                         }
                     }
+                    vec![method]
                 })
             })
             .collect()
     }
 
     async fn publish_properties(
-        timeout: Option<Duration>,
+        ctx: Ctx,
         base: Path,
-        publisher: Publisher,
-        proxy: Proxy<'_, Arc<SyncConnection>>,
+        proxy: Proxy<'static, Arc<SyncConnection>>,
         node: xml::Node,
+        errors: Errors,
         mut stop: future::Shared<oneshot::Receiver<()>>,
     ) -> Result<()> {
-        let (filter, mut changes): (
-            MsgMatch,
-            UnboundedReceiver<(Message, PropertiesPropertiesChanged)>,
-        ) = proxy
-            .connection
-            .add_match(
-                MatchRule::new()
-                    .with_sender(proxy.destination.clone().into_static())
-                    .with_path(proxy.path.clone().into_static())
-                    .with_interface("org.freedesktop.DBus.Properties")
-                    .with_member("PropertiesChanged"),
-            )
-            .await?
-            .stream();
-        let cleanup = {
-            let connection = proxy.connection.clone();
-            || async move {
-                let _: std::result::Result<_, _> = connection.remove_match(filter.token()).await;
-            }
-        };
-        let iface_properties = future::join_all(node.interfaces().into_iter().map(|i| {
+        const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+        let lazy = ctx.lazy_properties;
+        // unless it's lazy the match is added before GetAll so no
+        // change is missed in between
+        let mut filter = None;
+        if !lazy {
+            filter = Some(
+                ctx.add_match(&proxy, PROPERTIES, "PropertiesChanged")
+                    .await?,
+            );
+        }
+        let mut watch = ctx.interest.watch();
+        let (timeout, publisher) = (ctx.timeout, &ctx.publisher);
+        let interfaces = node
+            .interfaces()
+            .into_iter()
+            .filter(|i| !Self::check(&ctx, &proxy, &i.name, None).hidden());
+        let iface_properties = future::join_all(interfaces.map(|i| {
             let proxy = &proxy;
             async move {
                 let i = i.name.clone();
-                let props = proxy
-                    .get_all(&i)
-                    .await
-                    .map_err(|e| anyhow!("failed to look up properties for {}, {}", i, e))?;
-                Ok::<(String, arg::PropMap), anyhow::Error>((i, props))
+                let props = proxy.get_all(&i).await;
+                (i, props)
             }
         }))
         .await
         .into_iter()
-        .filter_map(|r| match r {
-            Ok((i, props)) => Some((i, props)),
+        .filter_map(|(i, r)| match r {
+            Ok(props) => Some((i, props)),
             Err(e) => {
-                warn!("{}", e);
+                let m = format!("failed to look up properties for {}, {}", i, e);
+                Self::failed(&ctx, &errors, &base, &i, m);
                 None
             }
         });
         let (tx_writes, mut rx_writes) = mpsc::channel(3);
-        let mut by_dbus: FxHashMap<String, FxHashMap<String, Val>> = HashMap::default();
+        let mut by_dbus: FxHashMap<String, FxHashMap<String, PublishedProperty>> =
+            HashMap::default();
         let mut by_id: FxHashMap<Id, (String, String, DbusType)> = HashMap::default();
         macro_rules! set_prop {
             ($i:expr, $name:expr, $value:expr, $by_name:expr) => {{
+                let action = Self::check(&ctx, &proxy, &$i, Some(&$name));
+                if action.hidden() {
+                    continue;
+                }
                 let path = base
                     .append("interfaces")
                     .append(&$i)
                     .append("properties")
                     .append(&$name);
-                let val = publisher.publish(path, dbus_value_to_netidx_value(&$value))?;
+                let init = ctx
+                    .policy()
+                    .redact
+                    .check(&$i, &$name, None)
+                    .apply(dbus_value_to_netidx_value(&$value));
+                // returning early would leak the match, so a property
+                // that can't be published is reported and skipped
+                let val = match ctx.publish(path, init) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        let m = format!("failed to publish property {} {}", $name, e);
+                        Self::failed(&ctx, &errors, &base, &$i, m);
+                        continue;
+                    }
+                };
+                ctx.publish_aliases(val.id(), &proxy, &$i, &$name);
                 let typ = match DbusType::from_str(&$value.signature()) {
                     Ok(typ) => typ,
                     Err(e) => {
@@ -738,101 +1930,184 @@ impl Object {
                         DbusType::Variant
                     }
                 };
-                publisher.writes(val.id(), tx_writes.clone());
+                if action.writable() {
+                    publisher.writes(val.id(), tx_writes.clone());
+                }
                 by_id.insert(val.id(), ($i.clone(), $name.clone(), typ));
-                $by_name.insert($name, val);
+                if lazy {
+                    watch.add(val.id());
+                }
+                $by_name.insert(
+                    $name,
+                    PublishedProperty {
+                        val,
+                        _held: ctx.stats.hold(stats::Gauge::Properties),
+                    },
+                );
             }};
         }
         for (i, props) in iface_properties {
-            let by_name = by_dbus.entry(i.clone()).or_insert_with(HashMap::default);
+            let by_name = by_dbus.entry(i.clone()).or_default();
             for (name, value) in props {
                 set_prop!(i, name, value, by_name)
             }
         }
-        loop {
+        macro_rules! apply_change {
+            ($change:expr, $batch:expr) => {{
+                let change: PropertiesPropertiesChanged = $change;
+                match by_dbus.get_mut(&change.interface_name) {
+                    None => {
+                        let intf = by_dbus
+                            .entry(change.interface_name.clone())
+                            .or_insert_with(HashMap::default);
+                        for (name, value) in change.changed_properties {
+                            set_prop!(change.interface_name, name, value, intf)
+                        }
+                    }
+                    Some(intf) => {
+                        for inv in &change.invalidated_properties {
+                            if let Some(prop) = intf.remove(inv) {
+                                by_id.remove(&prop.val.id());
+                                watch.remove(&prop.val.id());
+                            }
+                        }
+                        for (name, value) in change.changed_properties {
+                            match intf.get(&name) {
+                                Some(prop) => {
+                                    let mode = ctx.policy().redact.check(
+                                        &change.interface_name,
+                                        &name,
+                                        None,
+                                    );
+                                    prop.val.update(
+                                        $batch,
+                                        mode.apply(dbus_value_to_netidx_value(&value)),
+                                    )
+                                }
+                                None => set_prop!(change.interface_name, name, value, intf),
+                            }
+                        }
+                        if intf.len() == 0 {
+                            by_dbus.remove(&change.interface_name);
+                        }
+                    }
+                }
+            }};
+        }
+        let r = loop {
             let mut batch = publisher.start_batch();
+            if lazy {
+                let wanted = watch.subscribed();
+                match ctx
+                    .sync_match(&mut filter, wanted, &proxy, PROPERTIES, "PropertiesChanged")
+                    .await
+                {
+                    Err(e) => break Err(e),
+                    Ok(false) => (),
+                    // the values may have changed while nobody was looking
+                    Ok(true) => {
+                        for i in by_dbus.keys().cloned().collect::<Vec<_>>() {
+                            match proxy.get_all(&i).await {
+                                Err(e) => warn!("failed to refresh properties of {}, {}", i, e),
+                                Ok(props) => {
+                                    let change = PropertiesPropertiesChanged {
+                                        interface_name: i,
+                                        changed_properties: props,
+                                        invalidated_properties: vec![],
+                                    };
+                                    apply_change!(change, &mut batch)
+                                }
+                            }
+                        }
+                    }
+                }
+            }
             select_biased! {
                 mut writes = rx_writes.select_next_some() => {
+                    ctx.stats.add(stats::Gauge::PendingWrites, writes.len() as i64);
                     for write in writes.drain(..) {
-                        match by_id.get(&write.id) {
+                        let start = Instant::now();
+                        let (i, name, typ) = match by_id.get(&write.id) {
+                            Some(prop) => prop,
                             None => {
+                                ctx.stats.add(stats::Gauge::PendingWrites, -1);
                                 warn!("probably a bug: no such property for {:?}", write.id);
                                 if let Some(r) = write.send_result {
                                     r.send(Value::Error(Chars::from("no such property")))
                                 }
+                                continue
                             }
-                            Some((i, name, typ)) => match netidx_value_to_dbus_value(&write.value, &typ) {
-                                Err(e) => {
-                                    let m = format!("property type conversion failed {}", e);
-                                    warn!("{}", m);
-                                    if let Some(r) = write.send_result {
-                                        r.send(Value::Error(Chars::from(m)))
-                                    }
-                                },
+                        };
+                        let target = format!("{}:{} {}.{}", proxy.destination, proxy.path, i, name);
+                        let who = ctx.logins.who(write.client);
+                        let (op, p) = (audit::Op::Set, who.principals.as_slice());
+                        let (dest, path) = (&proxy.destination, &proxy.path);
+                        let r = if ctx.closing() {
+                            Err(String::from("shutting down"))
+                        } else if !Self::check(&ctx, &proxy, i, Some(name)).writable() {
+                            Err(String::from("read only"))
+                        } else if let Err(e) =
+                            ctx.policy().access.check(p, op, dest, Some(path), Some(i), Some(name))
+                        {
+                            Err(e)
+                        } else {
+                            match netidx_value_to_dbus_value(&write.value, typ) {
+                                Err(e) => Err(format!("property type conversion failed {}", e)),
                                 Ok(v) => {
                                     let r: MethodReply<()> = proxy.method_call(
                                         "org.freedesktop.DBus.Properties",
                                         "Set",
                                         (&i, &name, v)
                                     );
-                                    if let Err(e) = r.await {
-                                        let m = format!("property set error {}", e);
-                                        warn!("{}", m);
-                                        if let Some(r) = write.send_result {
-                                            r.send(Value::Error(Chars::from(m)))
-                                        }
-                                    }
+                                    r.await.map_err(|e| format!("property set error {}", e))
                                 }
                             }
+                        };
+                        let result = r.clone().map(|()| String::from("ok"));
+                        let mode = ctx.policy().redact.check(i, name, None);
+                        let value = mode.apply(write.value.clone()).to_string();
+                        if let Err(m) = &r {
+                            warn!("{} setting {} {}", who, target, m)
                         }
-                    }
-                },
-                (_, change) = changes.select_next_some() => match by_dbus.get_mut(&change.interface_name) {
-                    None => {
-                        let intf = by_dbus.entry(change.interface_name.clone()).or_insert_with(HashMap::default);
-                        for (name, value) in change.changed_properties {
-                            set_prop!(change.interface_name, name, value, intf)
-                        }
-                    }
-                    Some(intf) => {
-                        for inv in &change.invalidated_properties {
-                            if let Some(val) = intf.remove(inv) {
-                                by_id.remove(&val.id());
-                            }
-                        }
-                        for (name, value) in change.changed_properties {
-                            match intf.get(&name) {
-                                Some(val) => val.update(&mut batch, dbus_value_to_netidx_value(&value)),
-                                None => set_prop!(change.interface_name, name, value, intf)
+                        ctx.audit.record(who, op, target, value, start, result);
+                        ctx.stats.add(stats::Gauge::PendingWrites, -1);
+                        if let Err(m) = r {
+                            if let Some(r) = write.send_result {
+                                r.send(Value::Error(Chars::from(m)))
                             }
                         }
-                        if intf.len() == 0 {
-                            by_dbus.remove(&change.interface_name);
-                        }
                     }
                 },
-                _ = stop => {
-                    cleanup().await;
-                    break
-                },
-                complete => {
-                    cleanup().await;
-                    break
+                msg = next_match(&mut filter).fuse() => {
+                    if let Ok(change) = msg.read_all::<PropertiesPropertiesChanged>() {
+                        ctx.stats.tick(stats::Rate::PropertyUpdates, change.changed_properties.len() as u64);
+                        apply_change!(change, &mut batch)
+                    }
                 },
+                () = watch.changed().fuse() => (),
+                _ = stop => break Ok(()),
             }
             batch.commit(timeout).await
+        };
+        if let Some((sub, _)) = filter {
+            ctx.remove_match(sub).await
         }
-        Ok(())
+        ctx.retire(
+            by_dbus
+                .into_values()
+                .flat_map(|props| props.into_values())
+                .map(|prop| prop.val),
+        );
+        r
     }
 
     async fn publish_signal(
-        timeout: Option<Duration>,
+        ctx: Ctx,
         base: Path,
-        publisher: Publisher,
-        proxy: Proxy<'_, Arc<SyncConnection>>,
+        proxy: Proxy<'static, Arc<SyncConnection>>,
         interface: String,
         signal: String,
-        args: Vec<Value>,
+        args: Vec<String>,
         mut stop: future::Shared<oneshot::Receiver<()>>,
     ) -> Result<()> {
         let path = base
@@ -840,33 +2115,37 @@ impl Object {
             .append(&interface)
             .append("signals")
             .append(&signal);
-        let val = publisher.publish(path, Value::Null)?;
-        let (filter, mut signals) = proxy
-            .connection
-            .add_match(
-                MatchRule::new()
-                    .with_sender(proxy.destination.clone().into_static())
-                    .with_path(proxy.path.clone().into_static())
-                    .with_interface(interface.clone())
-                    .with_member(signal.clone()),
-            )
-            .await?
-            .msg_stream();
-        let cleanup = {
-            let connection = proxy.connection.clone();
-            || async move {
-                let _: std::result::Result<_, _> = connection.remove_match(filter.token()).await;
-            }
-        };
+        // nothing needs cleaning up yet, past here errors must break
+        // out of the loop so the match is removed
+        let val = ctx.publish(path, Value::Null)?;
+        ctx.publish_aliases(val.id(), &proxy, &interface, &signal);
+        let _held = ctx.stats.hold(stats::Gauge::Signals);
+        // the signal is only received while someone is subscribed
+        let mut watch = ctx.interest.watch();
+        watch.add(val.id());
+        let mut filter = None;
+        let (timeout, publisher) = (ctx.timeout, &ctx.publisher);
         let mut clients = Vec::new();
         let r = loop {
+            let wanted = watch.subscribed();
+            if let Err(e) = ctx
+                .sync_match(&mut filter, wanted, &proxy, &interface, &signal)
+                .await
+            {
+                break Err(e);
+            }
             let mut batch = publisher.start_batch();
             select_biased! {
-                signal = signals.select_next_some() => {
+                msg = next_match(&mut filter).fuse() => {
+                    ctx.stats.tick(stats::Rate::Signals, 1);
+                    let policy = ctx.policy();
                     let elts = Value::from(
                         args.iter()
-                            .zip(signal.iter_init())
-                            .map(|(a, v)| (a.clone(), dbus_value_to_netidx_value(&v)))
+                            .zip(msg.iter_init())
+                            .map(|(a, v)| {
+                                let mode = policy.redact.check(&interface, &signal, Some(a));
+                                (Value::from(a.clone()), mode.apply(dbus_value_to_netidx_value(&v)))
+                            })
                             .collect::<Vec<_>>()
                     );
                     publisher.put_subscribed(&val.id(), &mut clients);
@@ -874,24 +2153,35 @@ impl Object {
                         val.update_subscriber(&mut batch, cl, elts.clone());
                     }
                 }
+                () = watch.changed().fuse() => (),
                 _ = stop => break Ok(())
             }
             batch.commit(timeout).await
         };
-        cleanup().await;
+        if let Some((sub, _)) = filter {
+            ctx.remove_match(sub).await
+        }
+        ctx.retire(iter::once(val));
         r
     }
 
     fn publish_signals(
-        timeout: Option<Duration>,
+        ctx: &Ctx,
         base: Path,
-        publisher: Publisher,
         proxy: Proxy<'static, Arc<SyncConnection>>,
-        node: xml::Node,
+        node: &xml::Node,
+        errors: &Errors,
         stop: future::Shared<oneshot::Receiver<()>>,
-    ) {
+    ) -> Vec<task::JoinHandle<()>> {
+        let mut tasks = Vec::new();
         for i in node.interfaces() {
+            if Self::check(ctx, &proxy, &i.name, None).hidden() {
+                continue;
+            }
             for s in i.signals() {
+                if Self::check(ctx, &proxy, &i.name, Some(&s.name)).hidden() {
+                    continue;
+                }
                 let args =
                     s.args()
                         .into_iter()
@@ -900,234 +2190,818 @@ impl Object {
                             let name = a.name.clone().unwrap_or_else(|| {
                                 format!("anon{}", rand::thread_rng().gen::<u64>())
                             });
-                            Value::from(name)
+                            name
                         })
                         .collect::<Vec<_>>();
+                let ctx = ctx.clone();
                 let base = base.clone();
-                let publisher = publisher.clone();
                 let proxy = proxy.clone();
                 let i = i.name.clone();
                 let s = s.name.clone();
                 let stop = stop.clone();
-                task::spawn(async move {
-                    let r = Self::publish_signal(timeout, base, publisher, proxy, i, s, args, stop)
-                        .await;
+                let errors = errors.clone();
+                tasks.push(task::spawn(async move {
+                    let (b, p, n) = (base.clone(), proxy.clone(), s.clone());
+                    let r = Self::publish_signal(ctx.clone(), b, p, i.clone(), n, args, stop).await;
                     if let Err(e) = r {
-                        warn!("signal publisher failed {}", e);
+                        let m = format!("failed to publish signal {} {}", s, e);
+                        Self::failed(&ctx, &errors, &base, &i, m);
                     }
-                });
+                }));
             }
         }
+        tasks
     }
 
     fn new(
-        timeout: Option<Duration>,
-        base: Path,
-        publisher: Publisher,
-        proxy: Proxy<'static, Arc<SyncConnection>>,
-        stop: future::Shared<oneshot::Receiver<()>>,
-    ) -> Pin<Box<dyn Future<Output = Result<Object>>>> {
-        Box::into_pin(Box::new(async move {
-            let node = introspect(&proxy).await?;
-            if node
-                .interfaces()
-                .iter()
-                .any(|i| i.name.as_str() == "org.freedesktop.DBus.Properties")
-            {
-                let base = base.clone();
-                let publisher = publisher.clone();
-                let proxy = proxy.clone();
-                let node = node.clone();
-                let stop = stop.clone();
-                task::spawn(async move {
-                    let path = proxy.path.clone();
-                    let dest = proxy.destination.clone();
-                    match Self::publish_properties(timeout, base, publisher, proxy, node, stop)
-                        .await
-                    {
-                        Ok(()) => warn!("properties publisher for {}:{} stopped", dest, path),
-                        Err(e) => warn!("properties publisher for {}:{} failed {}", dest, path, e),
-                    }
-                });
-            }
-            Self::publish_signals(
-                timeout,
-                base.clone(),
-                publisher.clone(),
-                proxy.clone(),
-                node.clone(),
-                stop.clone(),
-            );
-            let _methods = Self::publish_methods(&base, &publisher, &proxy, &node);
-            let _children = future::join_all(
-                node.nodes()
-                    .into_iter()
-                    .map(|c| {
-                        let base = c
-                            .name
-                            .as_ref()
-                            .map(|n| base.append("children").append(n))
-                            .unwrap_or_else(|| base.clone());
-                        let path = strings::Path::new(
-                            c.name
-                                .as_ref()
-                                .map(|n| {
-                                    if &*proxy.path == "/" {
-                                        format!("/{}", n)
-                                    } else {
-                                        format!("{}/{}", proxy.path, n)
-                                    }
-                                })
-                                .unwrap_or_else(|| String::from(&*proxy.path)),
-                        )
-                        .map_err(|_| anyhow!("invalid path {}", base))?;
-                        let proxy = Proxy::new(
-                            proxy.destination.clone(),
-                            path,
-                            TIMEOUT,
-                            Arc::clone(&proxy.connection),
-                        );
-                        Ok::<_, anyhow::Error>(Self::new(
-                            timeout,
-                            base,
-                            publisher.clone(),
-                            proxy,
-                            stop.clone(),
-                        ))
-                    })
-                    .filter_map(|r| match r {
-                        Ok(f) => Some(f),
-                        Err(e) => {
-                            warn!("failed to proxy child {}", e);
-                            None
+        ctx: &Ctx,
+        base: &Path,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        node: &xml::Node,
+    ) -> Self {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let stop = stop_rx.shared();
+        let errors = Errors::default();
+        let mut tasks = Vec::new();
+        if node
+            .interfaces()
+            .iter()
+            .any(|i| i.name.as_str() == "org.freedesktop.DBus.Properties")
+        {
+            let ctx = ctx.clone();
+            let base = base.clone();
+            let proxy = proxy.clone();
+            let node = node.clone();
+            let stop = stop.clone();
+            let errors = errors.clone();
+            tasks.push(task::spawn(async move {
+                let path = proxy.path.clone();
+                let dest = proxy.destination.clone();
+                let r = Self::publish_properties(
+                    ctx.clone(),
+                    base.clone(),
+                    proxy.clone(),
+                    node.clone(),
+                    errors.clone(),
+                    stop,
+                )
+                .await;
+                match r {
+                    Ok(()) => warn!("properties publisher for {}:{} stopped", dest, path),
+                    Err(e) => {
+                        warn!("properties publisher for {}:{} failed {}", dest, path, e);
+                        for i in node.interfaces() {
+                            let visible = !Self::check(&ctx, &proxy, &i.name, None).hidden();
+                            if visible && !i.properties().is_empty() {
+                                let m = format!("failed to publish properties {}", e);
+                                errors.report(
+                                    &ctx,
+                                    base.append("interfaces").append(&i.name).append("status"),
+                                    m,
+                                )
+                            }
                         }
-                    }),
-            )
-            .await
-            .into_iter()
-            .filter_map(|r| match r {
-                Ok(o) => Some(o),
-                Err(e) => {
-                    warn!("failed to proxy child {}", e);
-                    None
+                    }
                 }
-            })
+            }));
+        }
+        tasks.extend(Self::publish_signals(
+            ctx,
+            base.clone(),
+            proxy.clone(),
+            node,
+            &errors,
+            stop,
+        ));
+        let methods = Self::publish_methods(ctx, base, proxy, node, &errors);
+        let docs = Self::publish_docs(ctx, base, proxy, node);
+        Interfaces {
+            methods,
+            docs,
+            errors,
+            tasks,
+            stop: Some(stop_tx),
+        }
+    }
+
+    fn empty() -> Self {
+        Interfaces {
+            methods: Vec::new(),
+            docs: Vec::new(),
+            errors: Errors::default(),
+            tasks: Vec::new(),
+            stop: None,
+        }
+    }
+
+    /// Unpublish everything, and wait until it is actually gone so
+    /// the same paths can be published again.
+    async fn shutdown(self, ctx: &Ctx) {
+        let Interfaces {
+            methods,
+            docs,
+            errors,
+            tasks,
+            stop,
+        } = self;
+        drop(stop);
+        ctx.retire(docs);
+        let paths = methods
+            .iter()
+            .flat_map(|m| m.paths.iter().cloned())
             .collect::<Vec<_>>();
-            Ok(Object {
-                _methods,
-                _children,
-            })
-        }))
+        drop(methods);
+        for t in tasks {
+            let _ = t.await;
+        }
+        errors.shutdown(ctx);
+        // rpcs are unpublished asynchronously by their own task
+        let deadline = Instant::now() + UNPUBLISH_TIMEOUT;
+        while paths.iter().any(|p| ctx.publisher.id(p).is_some()) && Instant::now() < deadline {
+            time::sleep(Duration::from_millis(10)).await
+        }
     }
 }
 
-struct ProxiedBusName {
-    _root: Object,
-    _stop: oneshot::Sender<()>,
+/// An object and it's ancestors, used to notice services that list
+/// an object as a descendant of itself
+struct Lineage {
+    path: String,
+    // a hash of the parsed introspection data
+    node: u64,
+    depth: usize,
+    parent: Option<Arc<Lineage>>,
 }
 
-impl ProxiedBusName {
-    async fn new(
-        timeout: Option<Duration>,
-        con: &Arc<SyncConnection>,
-        publisher: Publisher,
-        base: Path,
-        name: String,
-    ) -> Result<Self> {
-        let (_stop, receiver) = oneshot::channel();
-        let proxy = Proxy::new(name, "/", TIMEOUT, con.clone());
-        let _root = Object::new(timeout, base, publisher, proxy, receiver.shared()).await?;
-        Ok(ProxiedBusName { _root, _stop })
+impl Lineage {
+    /// Fails if an ancestor has the same name and introspection data
+    /// as the object, because then it's children would repeat forever
+    fn new(
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        node: &xml::Node,
+        parent: Option<Arc<Lineage>>,
+    ) -> result::Result<Arc<Self>, limits::Truncated> {
+        fn name(path: &str) -> &str {
+            path.rsplit('/').next().unwrap_or(path)
+        }
+        let path = String::from(&*proxy.path);
+        let hash = fxhash::hash64(node);
+        let mut ancestor = parent.as_deref();
+        while let Some(a) = ancestor {
+            if a.node == hash && name(&a.path) == name(&path) {
+                return Err(limits::Truncated::Cycle(a.path.clone()));
+            }
+            ancestor = a.parent.as_deref();
+        }
+        Ok(Arc::new(Lineage {
+            path,
+            node: hash,
+            depth: parent.as_ref().map(|p| p.depth + 1).unwrap_or(0),
+            parent,
+        }))
     }
 }
 
-struct Activatable {
-    by_id: FxHashMap<Id, String>,
-    by_name: FxHashMap<String, Val>,
-    publisher: Publisher,
-    con: Proxy<'static, Arc<SyncConnection>>,
-    activate: mpsc::Sender<Pooled<Vec<WriteRequest>>>,
+struct Object {
     base: Path,
+    proxy: Proxy<'static, Arc<SyncConnection>>,
+    node: xml::Node,
+    lineage: Arc<Lineage>,
+    interfaces: Interfaces,
+    children: FxHashMap<String, Object>,
+    errors: Errors,
+    refresh: Option<Val>,
+    _budget: limits::Taken,
+    _held: stats::Held,
 }
 
-impl Activatable {
-    async fn sync(&mut self) -> Result<()> {
-        let names = list_activatable_names(&self.con)
-            .await?
-            .into_iter()
-            .filter(|n| !n.starts_with(":"))
-            .collect::<FxHashSet<_>>();
-        for name in &names {
-            if !self.by_name.contains_key(name) {
-                let path = self.base.append(name);
-                let val = self.publisher.publish(path, Value::Null)?;
-                let id = val.id();
-                self.publisher.writes(id, self.activate.clone());
-                self.by_name.insert(name.clone(), val);
-                self.by_id.insert(id, name.clone());
-            }
-        }
-        let remove = self
-            .by_name
-            .keys()
-            .filter(|name| !names.contains(*name))
-            .cloned()
-            .collect::<Vec<_>>();
-        for name in remove {
-            if let Some(val) = self.by_name.remove(&name) {
-                self.by_id.remove(&val.id());
-            }
+impl Object {
+    fn child_path(proxy: &Proxy<'static, Arc<SyncConnection>>, name: &str) -> String {
+        if &*proxy.path == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", proxy.path, name)
         }
-        Ok(())
     }
 
-    async fn activate(&self, mut reqs: Pooled<Vec<WriteRequest>>) {
-        for req in reqs.drain(..) {
-            if let Some(name) = self.by_id.get(&req.id) {
-                let r: result::Result<(u32,), dbus::Error> = self
-                    .con
-                    .method_call("org.freedesktop.DBus", "StartServiceByName", (name, 0u32))
-                    .await;
-                match r {
-                    Err(e) => {
-                        warn!("failed to activate service {}", e);
-                        if let Some(r) = req.send_result {
-                            r.send(Value::Error(Chars::from(format!(
-                                "service activation failed: {}",
-                                e
-                            ))))
-                        }
+    /// The names of the children of `node` that aren't hidden by a
+    /// rule. Hiding an object also hides all of it's children.
+    fn children(
+        ctx: &Ctx,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        node: &xml::Node,
+    ) -> Vec<String> {
+        node.nodes()
+            .into_iter()
+            .filter_map(|c| c.name.clone())
+            .filter(|name| {
+                let path = Self::child_path(proxy, name);
+                !ctx.policy()
+                    .check(&proxy.destination, Some(&path), None, None)
+                    .hidden()
+            })
+            .collect()
+    }
+
+    fn child(
+        base: &Path,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        name: &str,
+    ) -> Result<(Path, Proxy<'static, Arc<SyncConnection>>)> {
+        let base = base.append("children").append(name);
+        let path = Self::child_path(proxy, name);
+        let path = strings::Path::new(path).map_err(|_| anyhow!("invalid path {}", base))?;
+        let proxy = Proxy::new(
+            proxy.destination.clone(),
+            path,
+            proxy.timeout,
+            Arc::clone(&proxy.connection),
+        );
+        Ok((base, proxy))
+    }
+
+    /// Publish the object and all it's children. If `cached` contains
+    /// introspection data for an object it will be used instead of
+    /// introspecting the object. `parent` is None for the root object.
+    fn new(
+        ctx: Ctx,
+        base: Path,
+        proxy: Proxy<'static, Arc<SyncConnection>>,
+        cached: Option<Arc<FxHashMap<String, xml::Node>>>,
+        parent: Option<Arc<Lineage>>,
+    ) -> BoxFuture<'static, Result<Object>> {
+        Box::pin(async move {
+            let budget = ctx.budget.take()?;
+            let node = match cached.as_ref().and_then(|c| c.get(&*proxy.path)) {
+                Some(node) => node.clone(),
+                None => ctx.introspect(&proxy).await?,
+            };
+            let lineage = Lineage::new(&proxy, &node, parent)?;
+            let interfaces = Interfaces::new(&ctx, &base, &proxy, &node);
+            let refresh = Self::publish_refresh(&ctx, &base);
+            let errors = Errors::default();
+            let names = Self::children(&ctx, &proxy, &node);
+            let children =
+                Self::new_children(&ctx, &base, &proxy, &lineage, &errors, names, cached).await;
+            Ok(Object {
+                base,
+                proxy,
+                node,
+                lineage,
+                interfaces,
+                children,
+                errors,
+                refresh,
+                _budget: budget,
+                _held: ctx.stats.hold(stats::Gauge::Objects),
+            })
+        })
+    }
+
+    /// Publish the children `names` of the object at `base`. Children
+    /// that fail are reported where they would have been, and children
+    /// cut off by a limit are counted in `truncated`.
+    async fn new_children(
+        ctx: &Ctx,
+        base: &Path,
+        proxy: &Proxy<'static, Arc<SyncConnection>>,
+        lineage: &Arc<Lineage>,
+        errors: &Errors,
+        names: Vec<String>,
+        cached: Option<Arc<FxHashMap<String, xml::Node>>>,
+    ) -> FxHashMap<String, Object> {
+        if !names.is_empty() && lineage.depth >= ctx.limits.depth {
+            let cut = vec![limits::Truncated::Depth(ctx.limits.depth); names.len()];
+            Self::truncated(ctx, errors, base, cut);
+            return HashMap::default();
+        }
+        let mut cut = Vec::new();
+        let children = future::join_all(names.into_iter().map(|name| {
+            let r = Self::child(base, proxy, &name);
+            let ctx = ctx.clone();
+            let cached = cached.clone();
+            let lineage = Arc::clone(lineage);
+            async move {
+                let r = match r {
+                    Err(e) => Err(e),
+                    Ok((base, proxy)) => Self::new(ctx, base, proxy, cached, Some(lineage)).await,
+                };
+                (name, r)
+            }
+        }))
+        .await
+        .into_iter()
+        .filter_map(|(name, r)| match r {
+            Ok(o) => Some((name, o)),
+            Err(e) => {
+                match e.downcast::<limits::Truncated>() {
+                    Ok(t) => cut.push(t),
+                    Err(e) => Self::child_failed(ctx, errors, base, &name, e),
+                }
+                None
+            }
+        })
+        .collect::<FxHashMap<_, _>>();
+        Self::truncated(ctx, errors, base, cut);
+        children
+    }
+
+    /// Publish how many children of the object at `base` were cut off
+    /// by limits and why, or unpublish it if none were
+    fn truncated(ctx: &Ctx, errors: &Errors, base: &Path, cut: Vec<limits::Truncated>) {
+        let path = base.append("truncated");
+        if cut.is_empty() {
+            return errors.clear(ctx, &path);
+        }
+        let mut counts: FxHashMap<limits::Truncated, usize> = HashMap::default();
+        for t in cut {
+            *counts.entry(t).or_insert(0) += 1;
+        }
+        let reason = counts
+            .iter()
+            .map(|(t, n)| format!("{} children not published, {}", n, t))
+            .collect::<Vec<_>>()
+            .join("; ");
+        info!("{} {}", base, reason);
+        errors.replace(ctx, path, reason)
+    }
+
+    /// Publish why the child `name` couldn't be published
+    fn child_failed(ctx: &Ctx, errors: &Errors, base: &Path, name: &str, e: anyhow::Error) {
+        warn!("failed to proxy child {} of {} {}", name, base, e);
+        let path = base.append("children").append(name).append("error");
+        errors.report(ctx, path, e.to_string())
+    }
+
+    /// Writing to `refresh` forces the object and it's children to be
+    /// introspected and republished. It isn't published in read only
+    /// mode, where nothing is writable.
+    fn publish_refresh(ctx: &Ctx, base: &Path) -> Option<Val> {
+        let tx = ctx.refresh.as_ref()?;
+        if ctx.policy().read_only {
+            return None;
+        }
+        match ctx.publish(base.append("refresh"), Value::Null) {
+            Ok(val) => {
+                ctx.publisher.writes(val.id(), tx.clone());
+                Some(val)
+            }
+            Err(e) => {
+                warn!("failed to publish refresh for {} {}", base, e);
+                None
+            }
+        }
+    }
+
+    /// Find the object published at `base`
+    fn find_mut(&mut self, base: &str) -> Option<&mut Object> {
+        if &*self.base == base {
+            Some(self)
+        } else {
+            self.children
+                .values_mut()
+                .find(|c| Path::is_parent(&c.base, base))?
+                .find_mut(base)
+        }
+    }
+
+    /// Introspect the object again, and republish it's interfaces if
+    /// they changed, if `force` is true, or if the policy changed from
+    /// `old` in a way that affects them. Children that were added or
+    /// removed are published or unpublished, and the rest are
+    /// revalidated in turn. If only the policy changed the object
+    /// isn't introspected again, the introspection data it was
+    /// published with is checked against the new policy.
+    fn revalidate(&mut self, ctx: Ctx, force: bool, old: Option<Arc<Policy>>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let node = match &old {
+                Some(_) if !force => self.node.clone(),
+                _ => match ctx.introspect(&self.proxy).await {
+                    Ok(node) => node,
+                    Err(e) => {
+                        let (dest, path) = (&self.proxy.destination, &self.proxy.path);
+                        warn!("failed to revalidate {}:{} {}", dest, path, e);
+                        return;
                     }
-                    Ok((i,)) if i == 1 => (), // success
-                    Ok((i,)) if i == 2 => {
-                        if let Some(r) = req.send_result {
-                            r.send(Value::Error(Chars::from("service is already running")))
+                },
+            };
+            let (dest, path) = (&self.proxy.destination, &self.proxy.path);
+            let policy_changed = match &old {
+                None => false,
+                Some(old) => ctx.policy().differs(old, dest, path, &node),
+            };
+            if force || policy_changed || node.interfaces() != self.node.interfaces() {
+                info!("{}:{} changed, republishing", dest, path);
+                let old = mem::replace(&mut self.interfaces, Interfaces::empty());
+                old.shutdown(&ctx).await;
+                self.interfaces = Interfaces::new(&ctx, &self.base, &self.proxy, &node);
+            }
+            let names = Self::children(&ctx, &self.proxy, &node)
+                .into_iter()
+                .collect::<FxHashSet<_>>();
+            let removed = self
+                .children
+                .keys()
+                .filter(|n| !names.contains(*n))
+                .cloned()
+                .collect::<Vec<_>>();
+            for name in removed {
+                let path = self.base.append("children").append(&name).append("error");
+                self.errors.clear(&ctx, &path);
+                if let Some(child) = self.children.remove(&name) {
+                    child.shutdown(ctx.clone()).await
+                }
+            }
+            future::join_all(
+                self.children
+                    .values_mut()
+                    .map(|c| c.revalidate(ctx.clone(), force, old.clone())),
+            )
+            .await;
+            let added = names
+                .into_iter()
+                .filter(|name| !self.children.contains_key(name))
+                .collect::<Vec<_>>();
+            for name in &added {
+                let path = self.base.append("children").append(name).append("error");
+                self.errors.clear(&ctx, &path);
+            }
+            let (base, proxy, lineage) = (&self.base, &self.proxy, &self.lineage);
+            let added =
+                Self::new_children(&ctx, base, proxy, lineage, &self.errors, added, None).await;
+            self.children.extend(added);
+            match (self.refresh.is_some(), ctx.policy().read_only) {
+                (true, true) => self.refresh = None,
+                (false, false) => self.refresh = Self::publish_refresh(&ctx, &self.base),
+                (_, _) => (),
+            }
+            self.node = node;
+        })
+    }
+
+    /// Unpublish the object and all it's children
+    fn shutdown(self, ctx: Ctx) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let Object {
+                interfaces,
+                children,
+                errors,
+                refresh,
+                ..
+            } = self;
+            future::join_all(children.into_values().map(|c| c.shutdown(ctx.clone()))).await;
+            interfaces.shutdown(&ctx).await;
+            errors.shutdown(&ctx);
+            ctx.retire(refresh)
+        })
+    }
+
+    /// Collect the introspection data of the object and all it's
+    /// children by object path
+    fn collect(&self, into: &mut FxHashMap<String, String>) {
+        into.insert(String::from(&*self.proxy.path), self.node.to_xml());
+        for c in self.children.values() {
+            c.collect(into)
+        }
+    }
+}
+
+/// Publishes a bus name. The tree of objects is owned by a task, which
+/// unpublishes it when the ProxiedBusName is dropped.
+struct ProxiedBusName {
+    stop: oneshot::Sender<()>,
+    task: task::JoinHandle<()>,
+    retain: Arc<AtomicBool>,
+}
+
+impl ProxiedBusName {
+    /// Look up the owner of `name` and publish it's tree. Names are
+    /// often claimed before the objects behind them are exported, so
+    /// if introspection fails it is retried with backoff. If there is
+    /// no cached tree to publish `starting` is released before the
+    /// crawl, which can take a long time.
+    async fn start(
+        ctx: &mut Ctx,
+        con: &Arc<SyncConnection>,
+        base: &Path,
+        name: &str,
+        starting: &mut Option<stats::Held>,
+    ) -> Result<(Object, Val, Option<cache::Owner>, bool)> {
+        let dbus = Proxy::new("org.freedesktop.DBus", "/", ctx.timing.call, con.clone());
+        let unique = get_name_owner(&dbus, name).await?;
+        ctx.owner = Some(Arc::from(unique.as_str()));
+        let owner = match &ctx.cache {
+            None => None,
+            Some(_) => {
+                let pid = connection_unix_process_id(&dbus, name).await.ok();
+                Some(cache::Owner::new(name, pid).await)
+            }
+        };
+        let cached = match (&ctx.cache, &owner) {
+            (Some(cache), Some(owner)) => cache.load(name, owner).await.map(Arc::new),
+            (_, _) => None,
+        };
+        if cached.is_none() {
+            *starting = None
+        }
+        let proxy = Proxy::new(String::from(name), "/", ctx.timing.call, con.clone());
+        let mut backoff = INTROSPECT_RETRY_MIN;
+        let mut tries = 1;
+        let root = loop {
+            let r = Object::new(
+                ctx.clone(),
+                base.clone(),
+                proxy.clone(),
+                cached.clone(),
+                None,
+            )
+            .await;
+            match r {
+                Ok(root) => break root,
+                // the limits won't be any different next time
+                Err(e) if e.is::<limits::Truncated>() => return Err(e),
+                Err(e) if tries < INTROSPECT_TRIES && !ctx.disconnected() => {
+                    info!(
+                        "failed to introspect {}, retrying in {:?} {}",
+                        name, backoff, e
+                    );
+                    time::sleep(backoff).await;
+                    backoff *= 2;
+                    tries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let unique = ctx.publish(base.append("owner"), Value::from(unique))?;
+        Ok((root, unique, owner, cached.is_some()))
+    }
+
+    /// Publish `name` under `base`. If `settle` is true the name was
+    /// just claimed, and it's tree is introspected again a few times
+    /// while the owner finishes starting up.
+    fn new(ctx: Ctx, con: &Arc<SyncConnection>, base: Path, name: String, settle: bool) -> Self {
+        let retain = Arc::new(AtomicBool::new(false));
+        let (tx_refresh, mut rx_refresh) = mpsc::channel(3);
+        let mut ctx = Ctx {
+            retain: retain.clone(),
+            refresh: Some(tx_refresh),
+            budget: limits::Budget::new(ctx.limits.objects),
+            ..ctx
+        };
+        let (stop_tx, stop) = oneshot::channel::<()>();
+        let con = con.clone();
+        let mut starting = Some(ctx.stats.hold(stats::Gauge::Starting));
+        let crawling = ctx.stats.hold(stats::Gauge::Crawling);
+        let task = task::spawn(async move {
+            // the policy the tree is published with, changes made
+            // while it is crawled are applied afterwards
+            let mut policy = ctx.policy.clone();
+            let mut published = ctx.policy();
+            let mut stop = stop.fuse();
+            let r = select_biased! {
+                _ = stop => return,
+                r = Self::start(&mut ctx, &con, &base, &name, &mut starting).fuse() => r,
+            };
+            drop(starting);
+            let (mut root, unique, owner, cached) = match r {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("failed to proxy bus name {}: {}", name, e);
+                    // publish why until the name goes away
+                    let errors = Errors::default();
+                    errors.report(&ctx, base.append("error"), e.to_string());
+                    drop(crawling);
+                    let _ = stop.await;
+                    return errors.shutdown(&ctx);
+                }
+            };
+            let _held = ctx.stats.hold(stats::Gauge::Names);
+            let mut stopped = false;
+            if cached {
+                stopped = or_stop(&mut stop, root.revalidate(ctx.clone(), false, None)).await;
+            }
+            drop(crawling);
+            if !stopped {
+                ctx.stats.resynced();
+                let mut stored = HashMap::default();
+                Self::store(&ctx, &name, owner.as_ref(), &root, &mut stored).await;
+                // values handed over by the previous owner that the
+                // new owner didn't publish are gone. Revalidating a
+                // big tree can take a long time, so it gives way to stop,
+                // and the name can be handed off or shut down promptly.
+                let mut forget = time::sleep(ctx.timing.retained).boxed().fuse();
+                let mut delay = SETTLE_MIN;
+                let mut settled = if settle && delay <= ctx.timing.settle {
+                    time::sleep(delay).boxed().fuse()
+                } else {
+                    future::Fuse::terminated()
+                };
+                loop {
+                    select_biased! {
+                        _ = stop => break,
+                        () = policy_changed(&mut policy).fuse() => {
+                            let old = mem::replace(&mut published, ctx.policy());
+                            let r = root.revalidate(ctx.clone(), false, Some(old));
+                            if or_stop(&mut stop, r).await {
+                                break
+                            }
+                            Self::store(&ctx, &name, owner.as_ref(), &root, &mut stored).await
+                        }
+                        mut reqs = rx_refresh.select_next_some() => {
+                            let refresh = async {
+                                for req in reqs.drain(..) {
+                                    let path = match ctx.publisher.path(req.id) {
+                                        None => continue,
+                                        Some(path) => path,
+                                    };
+                                    if let Some(obj) = Path::dirname(&path).and_then(|b| root.find_mut(b)) {
+                                        info!("refreshing {}:{}", name, obj.proxy.path);
+                                        obj.revalidate(ctx.clone(), true, None).await
+                                    }
+                                }
+                            };
+                            if or_stop(&mut stop, refresh).await {
+                                break
+                            }
+                            Self::store(&ctx, &name, owner.as_ref(), &root, &mut stored).await
+                        }
+                        () = settled => {
+                            if or_stop(&mut stop, root.revalidate(ctx.clone(), false, None)).await {
+                                break
+                            }
+                            Self::store(&ctx, &name, owner.as_ref(), &root, &mut stored).await;
+                            delay *= 2;
+                            if delay <= ctx.timing.settle {
+                                settled = time::sleep(delay).boxed().fuse();
+                            }
                         }
+                        () = forget => ctx.forget_retained_under(&base),
                     }
-                    Ok((i,)) => {
-                        warn!("unexpected service activation response {}", i);
-                        if let Some(r) = req.send_result {
-                            r.send(Value::Error(Chars::from(format!(
-                                "unexpected service activation response {}",
-                                i
-                            ))));
+                }
+            }
+            root.shutdown(ctx.clone()).await;
+            ctx.retire(iter::once(unique))
+        });
+        ProxiedBusName {
+            stop: stop_tx,
+            task,
+            retain,
+        }
+    }
+
+    /// Write the tree to the introspection cache, if there is one, and
+    /// it changed since it was last written to `stored`
+    async fn store(
+        ctx: &Ctx,
+        name: &str,
+        owner: Option<&cache::Owner>,
+        root: &Object,
+        stored: &mut FxHashMap<String, String>,
+    ) {
+        if let (Some(cache), Some(owner)) = (&ctx.cache, owner) {
+            let mut objects = HashMap::default();
+            root.collect(&mut objects);
+            if objects != *stored {
+                match cache.store(name, owner.clone(), objects.clone()).await {
+                    Ok(()) => *stored = objects,
+                    Err(e) => warn!("failed to write introspection cache for {} {}", name, e),
+                }
+            }
+        }
+    }
+
+    /// Unpublish the bus name, but keep it's values published so the
+    /// next owner of the name can take them over seamlessly.
+    async fn hand_off(self) {
+        self.retain.store(true, Ordering::Relaxed);
+        self.shutdown().await
+    }
+
+    /// Unpublish the bus name and wait until it is gone
+    async fn shutdown(self) {
+        let ProxiedBusName { stop, task, .. } = self;
+        drop(stop);
+        let _ = task.await;
+    }
+}
+
+struct Activatable {
+    by_id: FxHashMap<Id, String>,
+    writable: FxHashSet<Id>,
+    by_name: FxHashMap<String, Val>,
+    ctx: Ctx,
+    con: Proxy<'static, Arc<SyncConnection>>,
+    activate: mpsc::Sender<Pooled<Vec<WriteRequest>>>,
+    base: Path,
+}
+
+impl Activatable {
+    async fn sync(&mut self) -> Result<()> {
+        let names = list_activatable_names(&self.con)
+            .await?
+            .into_iter()
+            .filter(|n| !n.starts_with(":"))
+            .filter(|n| !self.ctx.policy().check(n, None, None, None).hidden())
+            .collect::<FxHashSet<_>>();
+        for name in &names {
+            if !self.by_name.contains_key(name) {
+                let path = self.base.append(name);
+                let val = self.ctx.publish(path, Value::Null)?;
+                let id = val.id();
+                self.by_name.insert(name.clone(), val);
+                self.by_id.insert(id, name.clone());
+            }
+            // only accept activations if the name isn't read only
+            let id = self.by_name[name].id();
+            let writable = self.writable(name);
+            if writable && self.writable.insert(id) {
+                self.ctx.publisher.writes(id, self.activate.clone());
+            } else if !writable && self.writable.remove(&id) {
+                self.ctx.publisher.stop_writes(id);
+            }
+        }
+        let remove = self
+            .by_name
+            .keys()
+            .filter(|name| !names.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in remove {
+            if let Some(val) = self.by_name.remove(&name) {
+                self.by_id.remove(&val.id());
+                self.writable.remove(&val.id());
+            }
+        }
+        Ok(())
+    }
+
+    fn writable(&self, name: &str) -> bool {
+        self.ctx.policy().check(name, None, None, None).writable()
+    }
+
+    async fn activate(&self, mut reqs: Pooled<Vec<WriteRequest>>) {
+        for req in reqs.drain(..) {
+            if let Some(name) = self.by_id.get(&req.id) {
+                let start = Instant::now();
+                let _pending = self.ctx.stats.hold(stats::Gauge::PendingWrites);
+                let who = self.ctx.logins.who(req.client);
+                let (op, p) = (audit::Op::Activate, who.principals.as_slice());
+                let r = if self.ctx.closing() {
+                    Err(String::from("shutting down"))
+                } else if !self.writable(name) {
+                    Err(String::from("read only"))
+                } else if let Err(e) = self
+                    .ctx
+                    .policy()
+                    .access
+                    .check(p, op, name, None, None, None)
+                {
+                    warn!("{} activating {} {}", who, name, e);
+                    Err(e)
+                } else {
+                    let r: result::Result<(u32,), dbus::Error> = self
+                        .con
+                        .method_call("org.freedesktop.DBus", "StartServiceByName", (name, 0u32))
+                        .await;
+                    match r {
+                        Err(e) => {
+                            warn!("failed to activate service {}", e);
+                            Err(format!("service activation failed: {}", e))
+                        }
+                        Ok((1,)) => Ok(String::from("started")),
+                        Ok((2,)) => Err(String::from("service is already running")),
+                        Ok((i,)) => {
+                            warn!("unexpected service activation response {}", i);
+                            Err(format!("unexpected service activation response {}", i))
                         }
                     }
+                };
+                let args = String::new();
+                self.ctx
+                    .audit
+                    .record(who, op, name.clone(), args, start, r.clone());
+                if let (Err(e), Some(res)) = (r, req.send_result) {
+                    res.send(Value::Error(Chars::from(e)))
                 }
             }
         }
     }
 
+    fn shutdown(self) {
+        self.ctx.retire(self.by_name.into_values())
+    }
+
     async fn new(
         base: Path,
-        publisher: Publisher,
+        ctx: Ctx,
         con: Proxy<'static, Arc<SyncConnection>>,
         activate: mpsc::Sender<Pooled<Vec<WriteRequest>>>,
     ) -> Result<Self> {
         let mut t = Self {
             by_id: HashMap::default(),
+            writable: HashSet::default(),
             by_name: HashMap::default(),
-            publisher,
+            ctx,
             con,
             activate,
             base,
@@ -1137,84 +3011,197 @@ impl Activatable {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
-    let opts = Params::from_args();
-    let timeout = opts.timeout.map(Duration::from_secs);
-    let (cfg, auth) = opts.common.load();
-    let (dbus, con) = if opts.system {
-        info!("connecting to the system bus");
-        dbus_tokio::connection::new_system_sync()?
+/// Bridge one connection to a bus to netidx under `base`. Returns
+/// when the connection is lost.
+async fn bridge_connection(
+    ctx: &Ctx,
+    base: &Path,
+    bus: &BusAddress,
+    starting: &mut Option<stats::Held>,
+    stop: future::Shared<oneshot::Receiver<()>>,
+) -> Result<()> {
+    info!("connecting to {}", bus);
+    let (io, con) = bus.connect().await?;
+    con.set_signal_match_mode(true);
+    let mut io = task::spawn(io);
+    let ctx = &Ctx {
+        cache: ctx.cache.as_ref().map(|c| Arc::new(c.bus(base))),
+        dispatch: Some(Arc::new(dispatch::Dispatcher::new(con.clone(), ctx.peer))),
+        ..ctx.clone()
+    };
+    let r = if ctx.peer {
+        serve_peer(ctx, base, con, &mut io, starting, stop).await
     } else {
-        info!("connecting to the session bus");
-        dbus_tokio::connection::new_session_sync()?
+        serve_connection(ctx, base, bus, con, &mut io, starting, stop).await
     };
-    con.set_signal_match_mode(true);
-    task::spawn(async move {
-        let res = dbus.await;
-        error!("lost connection to dbus {}", res);
-    });
-    let publisher = Publisher::new(cfg, auth, opts.bind).await?;
-    let base = opts.netidx_base.clone();
-    let dbus = Proxy::new("org.freedesktop.DBus", "/", TIMEOUT, Arc::clone(&con));
-    let dbus_signal_match = con
+    io.abort();
+    r
+}
+
+/// Publish the object tree of a peer. Without a bus there are no
+/// names, so the peer's root object is published at `base`.
+async fn serve_peer(
+    ctx: &Ctx,
+    base: &Path,
+    con: Arc<SyncConnection>,
+    io: &mut task::JoinHandle<IOResourceError>,
+    starting: &mut Option<stats::Held>,
+    mut stop: future::Shared<oneshot::Receiver<()>>,
+) -> Result<()> {
+    // the peer ignores the destination, but method calls need one
+    let ctx = &Ctx {
+        budget: limits::Budget::new(ctx.limits.objects),
+        ..ctx.clone()
+    };
+    let proxy = Proxy::new(PEER_NAME, "/", ctx.timing.call, con);
+    let root = Object::new(ctx.clone(), base.clone(), proxy, None, None).await?;
+    *starting = None;
+    ctx.set_disconnected(false);
+    let mut forget = time::sleep(ctx.timing.retained).boxed().fuse();
+    let stopped = loop {
+        select_biased! {
+            r = io.fuse() => {
+                match r {
+                    Ok(e) => error!("lost connection to peer {}", e),
+                    Err(e) => error!("lost connection to peer {}", e),
+                }
+                break false
+            }
+            _ = stop => break true,
+            () = forget => ctx.forget_retained(),
+        }
+    };
+    ctx.set_disconnected(!stopped);
+    root.shutdown(ctx.clone()).await;
+    Ok(())
+}
+
+async fn serve_connection(
+    ctx: &Ctx,
+    base: &Path,
+    bus: &BusAddress,
+    con: Arc<SyncConnection>,
+    io: &mut task::JoinHandle<IOResourceError>,
+    starting: &mut Option<stats::Held>,
+    mut stop: future::Shared<oneshot::Receiver<()>>,
+) -> Result<()> {
+    use dbus::nonblock::stdintf::org_freedesktop_dbus::RequestNameReply;
+    if let Some(name) = &ctx.own_name {
+        match con.request_name(&**name, false, false, true).await? {
+            RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => (),
+            _ => bail!("{} is owned by another instance of the bridge", name),
+        }
+    }
+    let dbus = Proxy::new(
+        "org.freedesktop.DBus",
+        "/",
+        ctx.timing.call,
+        Arc::clone(&con),
+    );
+    let (_dbus_signal_match, mut signals) = con
         .add_match(
             MatchRule::new()
                 .with_sender("org.freedesktop.DBus")
                 .with_type(dbus::MessageType::Signal),
         )
-        .await?;
-    let token = dbus_signal_match.token();
-    let (dbus_signal_match, mut signals) = dbus_signal_match.msg_stream();
+        .await?
+        .msg_stream();
     let (tx_activate, mut rx_activate) = mpsc::channel(3);
     let mut activatable = Activatable::new(
         base.append("activatable"),
-        publisher.clone(),
+        ctx.clone(),
         dbus.clone(),
         tx_activate,
     )
     .await?;
-    let names = list_names(&dbus)
-        .await?
+    let all_names = list_names(&dbus).await?;
+    let mut uniques = match &ctx.unique {
+        None => None,
+        Some(filters) => {
+            let base = base.append("connections").append("unique");
+            let con = Arc::clone(&con);
+            Some(unique::Uniques::new(ctx.clone(), con, base, filters.clone(), &all_names).await)
+        }
+    };
+    let names = all_names
         .into_iter()
-        .filter(|n| !n.starts_with(":"))
+        .filter(|n| !n.starts_with(":") && Some(n.as_str()) != ctx.own_name.as_deref())
         .collect::<HashSet<_>>();
-    let start_proxying = |name: String| {
+    let start_proxying = |name: String, settle: bool| {
         let base = base.append("connections").append(&name);
-        let con = &con;
-        let publisher = publisher.clone();
-        async move {
-            let r = ProxiedBusName::new(timeout, con, publisher, base, name.clone()).await;
-            match r {
-                Ok(o) => Some(o),
-                Err(e) => {
-                    warn!("failed to proxy bus name {}: {}", name, e);
-                    None
-                }
-            }
-        }
+        ProxiedBusName::new(ctx.clone(), &con, base, name, settle)
     };
-    let mut names = future::join_all(
-        names
-            .into_iter()
-            .map(|n| async { (n.clone(), start_proxying(n).await) }),
-    )
-    .await
-    .into_iter()
-    .filter_map(|(name, r)| r.map(move |r| (name, r)))
-    .collect::<FxHashMap<_, _>>();
-    loop {
+    let mut names = names
+        .into_iter()
+        .filter(|n| !ctx.policy().check(n, None, None, None).hidden())
+        .map(|n| (n.clone(), start_proxying(n, false)))
+        .collect::<FxHashMap<_, _>>();
+    // the names hold on to starting until they are published
+    *starting = None;
+    ctx.set_disconnected(false);
+    // anything that was retained and hasn't come back by now is gone
+    let mut forget = time::sleep(ctx.timing.retained).boxed().fuse();
+    let mut policy = ctx.policy.clone();
+    let stopped = loop {
         select_biased! {
+            r = io.fuse() => {
+                match r {
+                    Ok(e) => error!("lost connection to {} {}", bus, e),
+                    Err(e) => error!("lost connection to {} {}", bus, e),
+                }
+                break false
+            }
+            _ = stop => break true,
+            () = policy_changed(&mut policy).fuse() => match list_names(&dbus).await {
+                Err(e) => warn!("failed to list names {}", e),
+                Ok(all_names) => {
+                    let hidden = |n: &str| ctx.policy().check(n, None, None, None).hidden();
+                    let removed = names.keys().filter(|n| hidden(n)).cloned().collect::<Vec<_>>();
+                    for name in removed {
+                        if let Some(o) = names.remove(&name) {
+                            o.shutdown().await
+                        }
+                    }
+                    let own = |n: &str| Some(n) == ctx.own_name.as_deref();
+                    for name in all_names.iter().filter(|n| !n.starts_with(":") && !own(n) && !hidden(n)) {
+                        if let Entry::Vacant(e) = names.entry(name.clone()) {
+                            e.insert(start_proxying(name.clone(), false));
+                        }
+                    }
+                    if let Some(uniques) = &mut uniques {
+                        uniques.sync(&all_names).await
+                    }
+                    if let Err(e) = activatable.sync().await {
+                        warn!("failed to sync activatable names {}", e)
+                    }
+                }
+            },
             msg = signals.select_next_some() => {
                 match msg.member() {
                     None => (),
                     Some(m) if &*m == "NameOwnerChanged" => {
-                        if let Ok(up) = msg.read_all::<NameOwnerChanged>() {
-                            if up.new_owner.is_none() {
-                                names.remove(up.name.as_str());
-                            } else if up.old_owner.is_none() && !up.name.starts_with(":") {
-                                if let Some(o) = start_proxying(up.name.clone()).await {
+                        let up = msg.read_all::<NameOwnerChanged>();
+                        if let (Some(uniques), Ok(up)) = (&mut uniques, &up) {
+                            uniques.name_owner_changed(up).await
+                        }
+                        match up {
+                            Err(_) => (),
+                            Ok(up) if up.name.starts_with(":") => (),
+                            Ok(up) if Some(up.name.as_str()) == ctx.own_name.as_deref() => (),
+                            Ok(up) => {
+                                if let Some(o) = names.remove(up.name.as_str()) {
+                                    match up.new_owner {
+                                        None => o.shutdown().await,
+                                        Some(_) => {
+                                            // the name moved straight to a new owner
+                                            info!("{} has a new owner", up.name);
+                                            o.hand_off().await
+                                        }
+                                    }
+                                }
+                                let hidden = ctx.policy().check(&up.name, None, None, None).hidden();
+                                if up.new_owner.is_some() && !hidden {
+                                    let o = start_proxying(up.name.clone(), true);
                                     names.insert(up.name, o);
                                 }
                             }
@@ -1231,10 +3218,765 @@ async fn main() -> Result<()> {
             req = rx_activate.select_next_some() => {
                 activatable.activate(req).await;
             }
-            complete => break,
+            () = forget => ctx.forget_retained(),
+            complete => break false,
+        }
+    };
+    ctx.set_disconnected(!stopped);
+    activatable.shutdown();
+    future::join_all(names.into_values().map(|n| n.shutdown())).await;
+    if let Some(uniques) = uniques {
+        uniques.shutdown().await
+    }
+    Ok(())
+}
+
+/// Bridge one bus to netidx under `base`, reconnecting with backoff
+/// whenever the connection is lost, until `stop` fires. While the
+/// bus is disconnected it's values stay published as errors.
+async fn bridge(
+    ctx: Ctx,
+    base: Path,
+    bus: BusAddress,
+    starting: stats::Held,
+    mut stop: future::Shared<oneshot::Receiver<()>>,
+) {
+    use rand::Rng;
+    let ctx = Ctx {
+        retained: Arc::new(Retained::default()),
+        peer: matches!(bus, BusAddress::Peer(_)),
+        ..ctx
+    };
+    let mut backoff = RECONNECT_MIN;
+    // held until the first connection has started publishing, or failed
+    let mut starting = Some(starting);
+    loop {
+        match bridge_connection(&ctx, &base, &bus, &mut starting, stop.clone()).await {
+            Ok(()) => backoff = RECONNECT_MIN,
+            Err(e) => {
+                warn!("failed to bridge {} {}", bus, e);
+                backoff = cmp::min(backoff * 2, RECONNECT_MAX);
+            }
+        }
+        starting = None;
+        if stop.peek().is_some() {
+            break;
+        }
+        let wait = rand::thread_rng().gen_range(backoff / 2..=backoff);
+        info!("reconnecting to {} in {:?}", bus, wait);
+        select_biased! {
+            _ = stop => break,
+            () = time::sleep(wait).fuse() => (),
+        }
+    }
+    info!("stopped bridging {}", bus)
+}
+
+/// A bridge running in it's own task. Dropping it stops the bridge
+/// in the background, `stop` also waits until it is gone.
+struct Bridge {
+    stop: oneshot::Sender<()>,
+    task: task::JoinHandle<()>,
+}
+
+impl Bridge {
+    fn spawn<F, Fut>(f: F) -> Self
+    where
+        F: FnOnce(future::Shared<oneshot::Receiver<()>>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, rx) = oneshot::channel();
+        let task = task::spawn(f(rx.shared()));
+        Bridge { stop, task }
+    }
+
+    /// Stop the bridge, and wait up to STOP_TIMEOUT for it to finish,
+    /// so a stuck bus can't hold up shutdown or a restart
+    async fn stop(self) {
+        let Bridge { stop, mut task } = self;
+        drop(stop);
+        if time::timeout(STOP_TIMEOUT, &mut task).await.is_err() {
+            warn!(
+                "a bridge didn't stop within {:?}, aborting it",
+                STOP_TIMEOUT
+            );
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+/// Bridge the session bus of every user logind knows about under
+/// `base/users/<name>`. Users come and go with logind's UserNew and
+/// UserRemoved signals, a user's bus may not exist yet when they
+/// appear, in which case the bridge retries with backoff like any
+/// other lost bus. Returns an error if the connection to logind is
+/// lost, and Ok when `stop` fires.
+async fn bridge_users(
+    ctx: &Ctx,
+    base: &Path,
+    starting: &mut Option<stats::Held>,
+    mut stop: future::Shared<oneshot::Receiver<()>>,
+) -> Result<()> {
+    const LOGIN1: &str = "org.freedesktop.login1";
+    let (io, con) = dbus_tokio::connection::new_system_sync()?;
+    con.set_signal_match_mode(true);
+    let mut io = task::spawn(io);
+    let timeout = ctx.timing.call;
+    let logind = Proxy::new(LOGIN1, "/org/freedesktop/login1", timeout, Arc::clone(&con));
+    let (_logind_match, mut signals) = con
+        .add_match(
+            MatchRule::new()
+                .with_sender(LOGIN1)
+                .with_interface("org.freedesktop.login1.Manager")
+                .with_type(dbus::MessageType::Signal),
+        )
+        .await?
+        .msg_stream();
+    let start = |uid: u32, user: dbus::Path<'static>| {
+        let con = Arc::clone(&con);
+        let ctx = ctx.clone();
+        async move {
+            let user = Proxy::new(LOGIN1, user, timeout, con);
+            let name: String = user.get("org.freedesktop.login1.User", "Name").await?;
+            let gid: u32 = user.get("org.freedesktop.login1.User", "GID").await?;
+            info!("bridging the session bus of {}", name);
+            let base = base.append("users").append(&name);
+            let bus = BusAddress::User { uid, gid };
+            let starting = ctx.stats.hold(stats::Gauge::Starting);
+            Ok::<_, anyhow::Error>(Bridge::spawn(|stop| bridge(ctx, base, bus, starting, stop)))
+        }
+    };
+    let (users,): (Vec<(u32, String, dbus::Path<'static>)>,) = logind
+        .method_call("org.freedesktop.login1.Manager", "ListUsers", ())
+        .await?;
+    let mut bridges: FxHashMap<u32, Bridge> = HashMap::default();
+    for (uid, _, user) in users {
+        match start(uid, user).await {
+            Ok(b) => {
+                bridges.insert(uid, b);
+            }
+            Err(e) => warn!("failed to bridge the session bus of uid {} {}", uid, e),
+        }
+    }
+    *starting = None;
+    loop {
+        select_biased! {
+            _ = stop => {
+                io.abort();
+                future::join_all(bridges.into_values().map(Bridge::stop)).await;
+                break Ok(())
+            }
+            r = (&mut io).fuse() => {
+                match r {
+                    Ok(e) => bail!("lost connection to logind {}", e),
+                    Err(e) => bail!("lost connection to logind {}", e),
+                }
+            }
+            msg = signals.select_next_some() => match msg.member() {
+                Some(m) if &*m == "UserNew" => {
+                    if let Ok((uid, user)) = msg.read2::<u32, dbus::Path<'static>>() {
+                        if let Entry::Vacant(e) = bridges.entry(uid) {
+                            match start(uid, user).await {
+                                Ok(b) => {
+                                    e.insert(b);
+                                }
+                                Err(e) => {
+                                    warn!("failed to bridge the session bus of uid {} {}", uid, e)
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(m) if &*m == "UserRemoved" => {
+                    if let Ok((uid, _)) = msg.read2::<u32, dbus::Path<'static>>() {
+                        if let Some(b) = bridges.remove(&uid) {
+                            b.stop().await;
+                            info!("stopped bridging the session bus of uid {}", uid)
+                        }
+                    }
+                }
+                None | Some(_) => (),
+            },
+            complete => bail!("logind signals ended"),
+        }
+    }
+}
+
+/// Bridge the session buses of all users until `stop` fires,
+/// reconnecting to logind if the connection to it is lost
+async fn bridge_all_users(
+    ctx: Ctx,
+    base: Path,
+    starting: stats::Held,
+    mut stop: future::Shared<oneshot::Receiver<()>>,
+) {
+    let mut starting = Some(starting);
+    loop {
+        match bridge_users(&ctx, &base, &mut starting, stop.clone()).await {
+            Ok(()) => break,
+            Err(e) => error!("failed to bridge user session buses {}", e),
+        }
+        starting = None;
+        select_biased! {
+            _ = stop => break,
+            () = time::sleep(RECONNECT_MIN).fuse() => (),
+        }
+    }
+}
+
+/// The bridges running with one set of settings
+struct Running {
+    ctx: Ctx,
+    buses: FxHashMap<(Option<String>, BusAddress), Bridge>,
+    users: Option<Bridge>,
+}
+
+/// The parts of the bridge that outlive restarts, so the values they
+/// publish aren't lost while the old bridges are stopped
+struct Shared {
+    base: Path,
+    interest: interest::Interest,
+    audit: audit::Audit,
+    logins: access::Logins,
+    stats: stats::Stats,
+    // the current stats, for the systemd supervisor
+    tx_stats: watch::Sender<stats::Stats>,
+}
+
+impl Shared {
+    fn new(publisher: &Publisher, settings: &Settings) -> Self {
+        let stats = Self::stats(publisher, settings);
+        let audit = Self::audit(publisher, settings);
+        Shared {
+            base: settings.base.clone(),
+            interest: interest::Interest::new(publisher.clone()),
+            logins: Self::logins(publisher, settings, &audit),
+            audit,
+            tx_stats: watch::channel(stats.clone()).0,
+            stats,
+        }
+    }
+
+    fn audit(publisher: &Publisher, settings: &Settings) -> audit::Audit {
+        let base = settings.base.append(".audit");
+        let file = settings.audit_log.clone();
+        audit::Audit::new(publisher.clone(), base, file, settings.timeout)
+    }
+
+    fn logins(publisher: &Publisher, settings: &Settings, audit: &audit::Audit) -> access::Logins {
+        let base = settings.base.append(".access");
+        let logins = access::Logins::new(publisher.clone(), base, audit.clone());
+        logins.configure(&settings.policy.access);
+        logins
+    }
+
+    fn stats(publisher: &Publisher, settings: &Settings) -> stats::Stats {
+        let base = settings.base.append(".stats");
+        stats::Stats::new(publisher.clone(), base, settings.timeout)
+    }
+
+    /// Bring them in line with `settings`. They are only replaced if
+    /// the base moved, so the new values never collide with the old.
+    fn update(&mut self, publisher: &Publisher, settings: &Settings) {
+        if settings.base != self.base {
+            self.base = settings.base.clone();
+            self.audit = Self::audit(publisher, settings);
+            self.logins = Self::logins(publisher, settings, &self.audit);
+            self.stats = Self::stats(publisher, settings);
+            let _ = self.tx_stats.send(self.stats.clone());
+        } else {
+            self.audit
+                .configure(settings.audit_log.clone(), settings.timeout);
+            self.logins.configure(&settings.policy.access);
+            self.stats.set_timeout(settings.timeout);
+        }
+    }
+}
+
+impl Running {
+    /// Prepare to run bridges with `settings`, `update` starts them
+    async fn new(
+        publisher: &Publisher,
+        policy: &watch::Receiver<Arc<Policy>>,
+        shared: &Shared,
+        settings: &Settings,
+    ) -> Result<Self> {
+        let cache = match &settings.cache_dir {
+            None => None,
+            Some(dir) => Some(Arc::new(cache::Cache::new(dir.clone()).await?)),
+        };
+        let ctx = Ctx {
+            timeout: settings.timeout,
+            publisher: publisher.clone(),
+            cache,
+            retained: Arc::new(Retained::default()),
+            retain: Arc::new(AtomicBool::new(false)),
+            peer: false,
+            unique: settings.unique.clone(),
+            refresh: None,
+            policy: policy.clone(),
+            audit: shared.audit.clone(),
+            logins: shared.logins.clone(),
+            stats: shared.stats.clone(),
+            closing: Arc::new(AtomicBool::new(false)),
+            own_name: settings.own_name.as_deref().map(Arc::from),
+            dispatch: None,
+            interest: shared.interest.clone(),
+            lazy_properties: settings.lazy_properties,
+            limits: settings.limits,
+            timing: settings.timing,
+            introspecting: Arc::new(Semaphore::new(settings.limits.introspections.max(1))),
+            budget: limits::Budget::new(settings.limits.objects),
+            owner: None,
+        };
+        Ok(Running {
+            ctx,
+            buses: HashMap::default(),
+            users: None,
+        })
+    }
+
+    /// Stop and start bridges so the running buses match
+    /// `settings`. Everything else in `settings` must be the same as
+    /// it was when this was created.
+    async fn update(&mut self, settings: &Settings) {
+        let removed = self
+            .buses
+            .keys()
+            .filter(|k| !settings.buses.contains(k))
+            .cloned()
+            .collect::<Vec<_>>();
+        for k in removed {
+            if let Some(b) = self.buses.remove(&k) {
+                b.stop().await
+            }
+        }
+        for (subtree, bus) in &settings.buses {
+            if let Entry::Vacant(e) = self.buses.entry((subtree.clone(), bus.clone())) {
+                let base = match subtree {
+                    None => settings.base.clone(),
+                    Some(subtree) => settings.base.append(subtree),
+                };
+                let ctx = self.ctx.clone();
+                let bus = bus.clone();
+                let starting = self.ctx.stats.hold(stats::Gauge::Starting);
+                e.insert(Bridge::spawn(|stop| bridge(ctx, base, bus, starting, stop)));
+            }
+        }
+        match (settings.users, self.users.take()) {
+            (true, None) => {
+                let ctx = self.ctx.clone();
+                let base = settings.base.clone();
+                let starting = self.ctx.stats.hold(stats::Gauge::Starting);
+                let b = Bridge::spawn(|stop| bridge_all_users(ctx, base, starting, stop));
+                self.users = Some(b)
+            }
+            (true, Some(b)) => self.users = Some(b),
+            (false, Some(b)) => b.stop().await,
+            (false, None) => (),
+        }
+    }
+
+    async fn stop(self) {
+        let bridges = self.buses.into_values().chain(self.users);
+        future::join_all(bridges.map(Bridge::stop)).await;
+    }
+
+    /// Refuse new writes and calls, and wait up to `timeout` for the
+    /// ones in flight to finish
+    async fn drain(&self, timeout: Duration) {
+        self.ctx.closing.store(true, Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending = self.ctx.stats.get(stats::Gauge::PendingWrites);
+            if pending <= 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!("giving up on {} calls still in flight", pending);
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await
+        }
+    }
+}
+
+/// The signals that shut the bridge down
+struct Signals {
+    term: Signal,
+    int: Signal,
+}
+
+impl Signals {
+    fn new() -> Result<Self> {
+        Ok(Signals {
+            term: signal(SignalKind::terminate())?,
+            int: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        select_biased! {
+            _ = self.term.recv().fuse() => (),
+            _ = self.int.recv().fuse() => (),
+        }
+    }
+}
+
+/// Shut down cleanly, unpublishing everything and removing all our
+/// dbus matches. Another signal while this is in progress exits
+/// immediately.
+async fn shutdown(
+    publisher: Publisher,
+    running: Running,
+    mut signals: Signals,
+    notify: Option<&notify::Notify>,
+) {
+    info!("shutting down");
+    if let Some(notify) = notify {
+        notify.send("STOPPING=1")
+    }
+    task::spawn(async move {
+        signals.recv().await;
+        warn!("exiting immediately");
+        process::exit(1)
+    });
+    running.drain(SHUTDOWN_TIMEOUT).await;
+    running.stop().await;
+    publisher.flushed().await;
+    publisher.shutdown().await
+}
+
+/// Apply changed settings, only restarting the bridges that have to
+/// be restarted.
+async fn reconfigure(
+    publisher: &Publisher,
+    policy: &watch::Sender<Arc<Policy>>,
+    shared: &mut Shared,
+    running: &mut Running,
+    old: &Settings,
+    new: &Settings,
+) -> Result<()> {
+    if **policy.borrow() != new.policy {
+        info!("rules or aliases changed");
+        let _ = policy.send(Arc::new(new.policy.clone()));
+    }
+    shared.update(publisher, new);
+    let restart = new.base != old.base
+        || new.timeout != old.timeout
+        || new.cache_dir != old.cache_dir
+        || new.own_name != old.own_name
+        || new.lazy_properties != old.lazy_properties
+        || new.limits != old.limits
+        || new.timing != old.timing
+        || new.unique != old.unique;
+    if restart {
+        info!("restarting all bridges");
+        let next = Running::new(publisher, &policy.subscribe(), shared, new).await?;
+        mem::replace(running, next).stop().await;
+    }
+    running.update(new).await;
+    Ok(())
+}
+
+/// Keeps systemd up to date with how the bridge is doing. It is told
+/// the bridge is ready once the initial crawl of every bus is done,
+/// or with `--ready-before-crawl` once every bus is connected and the
+/// cached trees are published. After that the status says how much is
+/// published and how many bus names are still being crawled, and the
+/// watchdog, if it is enabled, is pinged along with it.
+struct Supervisor {
+    notify: notify::Notify,
+    stats: watch::Receiver<stats::Stats>,
+    watchdog: Option<Duration>,
+    before_crawl: bool,
+    ready: bool,
+}
+
+impl Supervisor {
+    fn new(
+        notify: notify::Notify,
+        stats: watch::Receiver<stats::Stats>,
+        before_crawl: bool,
+    ) -> Self {
+        Supervisor {
+            notify,
+            stats,
+            watchdog: notify::Notify::watchdog(),
+            before_crawl,
+            ready: false,
+        }
+    }
+
+    fn status(&self) -> String {
+        let stats = self.stats.borrow();
+        let mut status = format!(
+            "STATUS=publishing {} bus names, {} objects, {} properties",
+            stats.get(stats::Gauge::Names),
+            stats.get(stats::Gauge::Objects),
+            stats.get(stats::Gauge::Properties)
+        );
+        match stats.get(stats::Gauge::Crawling) {
+            0 => (),
+            n => status.push_str(&format!(", crawling {} bus names", n)),
+        }
+        status
+    }
+
+    /// How long until `tick` should be called again
+    fn interval(&self) -> Duration {
+        match (self.ready, self.watchdog) {
+            (false, _) => READY_POLL,
+            (true, None) => STATUS_INTERVAL,
+            (true, Some(w)) => cmp::min(w / 2, STATUS_INTERVAL),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.ready {
+            match self.watchdog {
+                None => self.notify.send(&self.status()),
+                Some(_) => self.notify.send(&format!("WATCHDOG=1\n{}", self.status())),
+            }
+        } else {
+            let ready = {
+                let stats = self.stats.borrow();
+                stats.get(stats::Gauge::Starting) == 0
+                    && (self.before_crawl || stats.get(stats::Gauge::Crawling) == 0)
+            };
+            if ready {
+                self.notify.send(&format!("READY=1\n{}", self.status()));
+                self.ready = true;
+            }
+        }
+    }
+}
+
+/// Apply changes to the config file, or the rules file, as they
+/// happen. This only returns if the status can't be published.
+async fn watch_config(
+    opts: &BridgeParams,
+    config: Option<&std::path::Path>,
+    publisher: &Publisher,
+    tx_policy: &watch::Sender<Arc<Policy>>,
+    shared: &mut Shared,
+    running: &mut Running,
+    mut settings: Settings,
+) -> Result<()> {
+    // the result of the last attempt to load the config
+    let status_path = |s: &Settings| s.base.append(".config").append("status");
+    let mut status = publisher.publish(status_path(&settings), Value::from("ok"))?;
+    let mut last_error = None;
+    loop {
+        time::sleep(CONFIG_POLL).await;
+        let r = match Settings::load(opts, config).await {
+            Err(e) => Err(e),
+            Ok(new) if new == settings => Ok(()),
+            Ok(new) => {
+                info!("settings changed, reconfiguring");
+                let r = reconfigure(publisher, tx_policy, shared, running, &settings, &new).await;
+                if r.is_ok() {
+                    if new.base != settings.base {
+                        drop(status);
+                        status = publisher.publish(status_path(&new), Value::from("ok"))?;
+                    }
+                    settings = new;
+                }
+                r
+            }
+        };
+        let error = r.err().map(|e| e.to_string());
+        if error != last_error {
+            let v = match &error {
+                None => Value::from("ok"),
+                Some(e) => {
+                    error!("failed to apply config {}", e);
+                    Value::Error(Chars::from(e.clone()))
+                }
+            };
+            let mut batch = publisher.start_batch();
+            status.update(&mut batch, v);
+            batch.commit(settings.timeout).await;
+            last_error = error;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opts = Params::from_args();
+    let config = opts.bridge_config.as_deref();
+    let settings = Settings::load(&opts.bridge, config).await?;
+    let (cfg, auth) = opts.common.load();
+    let publisher = Publisher::new(cfg, auth, opts.bind).await?;
+    let (tx_policy, rx_policy) = watch::channel(Arc::new(settings.policy.clone()));
+    let mut shared = Shared::new(&publisher, &settings);
+    let mut running = Running::new(&publisher, &rx_policy, &shared, &settings).await?;
+    running.update(&settings).await;
+    let mut signals = Signals::new()?;
+    let mut supervisor = notify::Notify::from_env()?.map(|notify| {
+        let stats = shared.tx_stats.subscribe();
+        Supervisor::new(notify, stats, opts.ready_before_crawl)
+    });
+    let watch = if config.is_none() && opts.bridge.rules_file.is_none() {
+        future::pending().boxed()
+    } else {
+        let bridge = &opts.bridge;
+        watch_config(
+            bridge,
+            config,
+            &publisher,
+            &tx_policy,
+            &mut shared,
+            &mut running,
+            settings,
+        )
+        .boxed()
+    };
+    let mut watch = watch.fuse();
+    loop {
+        let tick = match &supervisor {
+            None => future::pending().boxed(),
+            Some(s) => time::sleep(s.interval()).boxed(),
+        };
+        select_biased! {
+            () = signals.recv().fuse() => break,
+            r = watch => {
+                r?;
+                break;
+            }
+            () = tick.fuse() => {
+                if let Some(s) = &mut supervisor {
+                    s.tick()
+                }
+            }
         }
     }
-    dbus.connection.remove_match(token).await?;
-    drop(dbus_signal_match);
+    drop(watch);
+    let notify = supervisor.as_ref().map(|s| &s.notify);
+    shutdown(publisher, running, signals, notify).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(args: &[&str]) -> BridgeParams {
+        BridgeParams::from_iter_safe(iter::once("netidx-dbus").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        let mut opts = params(&["--call-timeout", "10", "--max-depth", "5", "--read-only"]);
+        let cfg = config::Config {
+            call_timeout: Some(20),
+            read_only: Some(false),
+            rules: Some(vec![String::from("hide name=org.example.*")]),
+            ..Default::default()
+        };
+        opts.merge(cfg).unwrap();
+        assert_eq!(opts.call_timeout, 20);
+        assert_eq!(opts.max_depth, 5);
+        assert!(!opts.read_only);
+        assert_eq!(opts.rule.len(), 1);
+        let cfg = config::Config {
+            address: Some(vec![String::from("bogus")]),
+            ..Default::default()
+        };
+        assert!(params(&[]).merge(cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn load() {
+        let file = std::env::temp_dir().join(format!("netidx-dbus-test-{}.toml", process::id()));
+        let opts = params(&[
+            "--call-timeout",
+            "10",
+            "--max-depth",
+            "5",
+            "--address",
+            "system",
+        ]);
+        std::fs::write(
+            &file,
+            "call-timeout = 20\naddress = [\"session\", \"system\"]\n",
+        )
+        .unwrap();
+        let settings = Settings::load(&opts, Some(&file)).await.unwrap();
+        assert_eq!(settings.timing.call, Duration::from_secs(20));
+        assert_eq!(settings.limits.depth, 5);
+        assert_eq!(settings.buses.len(), 2);
+        // keys that are removed fall back to the command line
+        std::fs::write(&file, "max-depth = 7\n").unwrap();
+        let settings = Settings::load(&opts, Some(&file)).await.unwrap();
+        assert_eq!(settings.timing.call, Duration::from_secs(10));
+        assert_eq!(settings.limits.depth, 7);
+        assert_eq!(settings.buses, vec![(None, BusAddress::System)]);
+        std::fs::write(&file, "no-such-setting = 1\n").unwrap();
+        assert!(Settings::load(&opts, Some(&file)).await.is_err());
+        std::fs::remove_file(&file).unwrap();
+        assert!(Settings::load(&opts, Some(&file)).await.is_err());
+        assert_eq!(
+            Settings::load(&opts, None).await.unwrap().timing.call,
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn bus_spec() {
+        let spec = |s: &str| s.parse::<BusSpec>().map(|b| (b.subtree, b.address));
+        let addr = |a: &str| BusAddress::Address(String::from(a));
+        assert_eq!(spec("session").unwrap(), (None, BusAddress::Session));
+        assert_eq!(
+            spec("host=system").unwrap(),
+            (Some(String::from("host")), BusAddress::System)
+        );
+        // an address contains '=', but the part before it has a ':'
+        assert_eq!(
+            spec("unix:path=/run/bus").unwrap(),
+            (None, addr("unix:path=/run/bus"))
+        );
+        assert_eq!(
+            spec("app=unix:path=/run/bus").unwrap(),
+            (Some(String::from("app")), addr("unix:path=/run/bus"))
+        );
+        assert!(spec("bogus").is_err());
+        assert!(spec("=system").is_err());
+        assert!(spec("a/b=system").is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        let buses = |args: &[&str]| BusSpec::resolve(&params(args));
+        assert_eq!(buses(&[]).unwrap(), vec![(None, BusAddress::Session)]);
+        assert_eq!(
+            buses(&["--system"]).unwrap(),
+            vec![(None, BusAddress::System)]
+        );
+        // with more than one bus they get their own subtrees
+        let both = buses(&["--address", "session", "--system"]).unwrap();
+        assert_eq!(
+            both,
+            vec![
+                (Some(String::from("session")), BusAddress::Session),
+                (Some(String::from("system")), BusAddress::System)
+            ]
+        );
+        assert!(buses(&["--address", "session", "--address", "unix:path=/a"]).is_err());
+        let peer = buses(&["--address", "session", "--peer", "app=unix:path=/a"]).unwrap();
+        assert_eq!(
+            peer[1],
+            (
+                Some(String::from("app")),
+                BusAddress::Peer(String::from("unix:path=/a"))
+            )
+        );
+        assert!(buses(&["--peer", "system"]).is_err());
+        assert!(buses(&["--address", "a=session", "--address", "a=system"]).is_err());
+        assert!(buses(&["--users", "--address", "users=system"]).is_err());
+        assert!(buses(&["--users"]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::Duration,
+};
+
+/// The systemd service manager, if we were started by it with
+/// `Type=notify`
+pub struct Notify {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Notify {
+    /// Connect to the socket named by `NOTIFY_SOCKET`, if it is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let path = match env::var_os("NOTIFY_SOCKET") {
+            None => return Ok(None),
+            Some(path) => path
+                .into_string()
+                .map_err(|_| anyhow!("invalid NOTIFY_SOCKET"))?,
+        };
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        Ok(Some(Notify { socket, addr }))
+    }
+
+    /// How often the watchdog must be pinged, if it is enabled for us
+    pub fn watchdog() -> Option<Duration> {
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok()? != process::id() {
+                return None;
+            }
+        }
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        Some(Duration::from_micros(usec))
+    }
+
+    /// Send newline separated `state` assignments, e.g. `READY=1`
+    pub fn send(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            warn!("failed to notify systemd {}", e)
+        }
+    }
+}
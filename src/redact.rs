@@ -0,0 +1,235 @@
+use anyhow::{anyhow, bail, Result};
+use globset::{Glob, GlobMatcher};
+use netidx::subscriber::Value;
+use std::{collections::hash_map::RandomState, hash::BuildHasher, str::FromStr, sync::OnceLock};
+
+/// Applied after the user's redactions unless they are turned off. A
+/// rule without an `arg` covers every argument and the return value
+/// of a method, and every argument of a signal.
+const DEFAULTS: [&str; 10] = [
+    // secret service
+    "placeholder interface=org.freedesktop.Secret.* member=*Secret*",
+    "placeholder interface=org.freedesktop.Secret.* arg=secret*",
+    // network manager and wpa supplicant keys
+    "placeholder interface=org.freedesktop.NetworkManager* member=*Secrets",
+    "placeholder interface=org.freedesktop.NetworkManager* arg=connection",
+    "placeholder interface=org.freedesktop.NetworkManager.Settings.Connection member=Update*",
+    "placeholder interface=fi.w1.wpa_supplicant1.Network member=Properties",
+    "placeholder interface=fi.w1.wpa_supplicant1.Interface member=AddNetwork",
+    // contacts and calendars
+    "hash interface=org.gnome.evolution.dataserver.AddressBook*",
+    "hash interface=org.gnome.evolution.dataserver.Calendar*",
+    "hash interface=org.freedesktop.Telepathy.Connection.Interface.ContactInfo*",
+];
+
+const PLACEHOLDER: &str = "<redacted>";
+
+/// What happens to a value a redaction matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// leave it alone, e.g. to exempt something from a default
+    Keep,
+    /// replace it with a fixed placeholder
+    Placeholder,
+    /// replace it with a hash, so changes are still visible. The key
+    /// is chosen at random when the bridge starts.
+    Hash,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Mode::Keep),
+            "placeholder" => Ok(Mode::Placeholder),
+            "hash" => Ok(Mode::Hash),
+            m => bail!(
+                "unknown redaction {}, expected keep, placeholder, or hash",
+                m
+            ),
+        }
+    }
+}
+
+impl Mode {
+    pub fn redacted(self) -> bool {
+        self != Mode::Keep
+    }
+
+    pub fn apply(self, v: Value) -> Value {
+        static KEY: OnceLock<RandomState> = OnceLock::new();
+        match self {
+            Mode::Keep => v,
+            Mode::Placeholder => Value::from(PLACEHOLDER),
+            Mode::Hash => {
+                let h = KEY.get_or_init(RandomState::new).hash_one(v.to_string());
+                Value::from(format!("hash:{:016x}", h))
+            }
+        }
+    }
+}
+
+/// A redaction is written as a mode followed by globs that must all
+/// match, e.g. `placeholder interface=org.freedesktop.Secret.Item
+/// member=GetSecret` or `hash interface=*.AddressBook arg=uids`. The
+/// member is the name of a property, method, or signal, and arg the
+/// name of a method or signal argument. A field the redaction doesn't
+/// mention matches anything.
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    mode: Mode,
+    interface: Option<GlobMatcher>,
+    member: Option<GlobMatcher>,
+    arg: Option<GlobMatcher>,
+}
+
+impl FromStr for Redaction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let mode = parts
+            .next()
+            .ok_or_else(|| anyhow!("empty redaction"))?
+            .parse::<Mode>()?;
+        let mut redaction = Redaction {
+            mode,
+            interface: None,
+            member: None,
+            arg: None,
+        };
+        for part in parts {
+            let (key, glob) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected <field>=<glob>, got {}", part))?;
+            let glob = Some(Glob::new(glob)?.compile_matcher());
+            match key {
+                "interface" => redaction.interface = glob,
+                "member" => redaction.member = glob,
+                "arg" => redaction.arg = glob,
+                k => bail!("unknown field {}, expected interface, member, or arg", k),
+            }
+        }
+        Ok(redaction)
+    }
+}
+
+impl Redaction {
+    fn matches(&self, interface: &str, member: &str, arg: Option<&str>) -> bool {
+        let is_match = |glob: &Option<GlobMatcher>, v: &str| match glob {
+            None => true,
+            Some(glob) => glob.is_match(v),
+        };
+        is_match(&self.interface, interface)
+            && is_match(&self.member, member)
+            && match (&self.arg, arg) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(glob), Some(arg)) => glob.is_match(arg),
+            }
+    }
+}
+
+impl PartialEq for Redaction {
+    fn eq(&self, other: &Self) -> bool {
+        fn glob(g: &Option<GlobMatcher>) -> Option<&Glob> {
+            g.as_ref().map(|g| g.glob())
+        }
+        self.mode == other.mode
+            && glob(&self.interface) == glob(&other.interface)
+            && glob(&self.member) == glob(&other.member)
+            && glob(&self.arg) == glob(&other.arg)
+    }
+}
+
+/// An ordered list of redactions, the first one that matches decides
+/// what happens to a value, and if none match it is kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redactions(Vec<Redaction>);
+
+impl Redactions {
+    /// `redactions` followed by the built in defaults if `defaults`
+    pub fn new(mut redactions: Vec<Redaction>, defaults: bool) -> Self {
+        if defaults {
+            redactions.extend(DEFAULTS.iter().map(|r| r.parse::<Redaction>().unwrap()));
+        }
+        Redactions(redactions)
+    }
+
+    /// Decide what to do with the value of a property (arg is None),
+    /// or an argument of a method or signal.
+    pub fn check(&self, interface: &str, member: &str, arg: Option<&str>) -> Mode {
+        self.0
+            .iter()
+            .find(|r| r.matches(interface, member, arg))
+            .map(|r| r.mode)
+            .unwrap_or(Mode::Keep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactions(redactions: &[&str], defaults: bool) -> Redactions {
+        let redactions = redactions.iter().map(|r| r.parse().unwrap()).collect();
+        Redactions::new(redactions, defaults)
+    }
+
+    #[test]
+    fn check() {
+        let r = redactions(
+            &[
+                "keep interface=org.freedesktop.Secret.Service member=OpenSession",
+                "hash interface=*.AddressBook arg=uids",
+                "placeholder interface=org.example.Vault member=Get*",
+            ],
+            true,
+        );
+        let secret = "org.freedesktop.Secret.Service";
+        assert_eq!(r.check(secret, "OpenSession", None), Mode::Keep);
+        assert_eq!(r.check(secret, "GetSecrets", None), Mode::Placeholder);
+        assert_eq!(r.check(secret, "Lock", Some("secret")), Mode::Placeholder);
+        assert_eq!(r.check(secret, "Lock", Some("objects")), Mode::Keep);
+        let book = "org.example.AddressBook";
+        assert_eq!(r.check(book, "Open", Some("uids")), Mode::Hash);
+        assert_eq!(r.check(book, "Open", Some("flags")), Mode::Keep);
+        // a redaction with an arg doesn't apply to properties
+        assert_eq!(r.check(book, "uids", None), Mode::Keep);
+        let vault = "org.example.Vault";
+        assert_eq!(r.check(vault, "GetKey", None), Mode::Placeholder);
+        assert_eq!(r.check(vault, "GetKey", Some("id")), Mode::Placeholder);
+        assert_eq!(r.check(vault, "SetKey", Some("id")), Mode::Keep);
+        let cal = "org.gnome.evolution.dataserver.Calendar8";
+        assert_eq!(r.check(cal, "Open", None), Mode::Hash);
+        let r = redactions(&[], false);
+        assert_eq!(r.check(secret, "GetSecrets", None), Mode::Keep);
+    }
+
+    #[test]
+    fn apply() {
+        let v = || Value::from("hunter2");
+        assert_eq!(Mode::Keep.apply(v()), v());
+        assert_eq!(Mode::Placeholder.apply(v()), Value::from(PLACEHOLDER));
+        let h = Mode::Hash.apply(v());
+        assert_ne!(h, v());
+        assert!(h.to_string().contains("hash:"));
+        assert!(!h.to_string().contains("hunter2"));
+        assert_eq!(Mode::Hash.apply(v()), h);
+        assert_ne!(Mode::Hash.apply(Value::from("hunter3")), h);
+        assert!(!Mode::Keep.redacted());
+        assert!(Mode::Placeholder.redacted() && Mode::Hash.redacted());
+    }
+
+    #[test]
+    fn parse() {
+        assert!("".parse::<Redaction>().is_err());
+        assert!("erase member=a".parse::<Redaction>().is_err());
+        assert!("hash path=/a".parse::<Redaction>().is_err());
+        assert!("hash member".parse::<Redaction>().is_err());
+        for r in DEFAULTS {
+            assert!(r.parse::<Redaction>().is_ok(), "{}", r)
+        }
+    }
+}
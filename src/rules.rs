@@ -0,0 +1,259 @@
+use anyhow::{anyhow, bail, Result};
+use globset::{Glob, GlobMatcher};
+use std::{path::Path, str::FromStr};
+use tokio::fs;
+
+/// What happens to the things a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// publish normally
+    Publish,
+    /// publish, but don't accept property writes or publish methods
+    ReadOnly,
+    /// publish, but don't publish methods
+    NoMethods,
+    /// don't publish at all
+    Hide,
+}
+
+impl Action {
+    pub fn hidden(self) -> bool {
+        self == Action::Hide
+    }
+
+    pub fn writable(self) -> bool {
+        match self {
+            Action::Publish | Action::NoMethods => true,
+            Action::ReadOnly | Action::Hide => false,
+        }
+    }
+
+    pub fn methods(self) -> bool {
+        self == Action::Publish
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "publish" => Ok(Action::Publish),
+            "read-only" => Ok(Action::ReadOnly),
+            "no-methods" => Ok(Action::NoMethods),
+            "hide" => Ok(Action::Hide),
+            a => bail!(
+                "unknown action {}, expected publish, read-only, no-methods, or hide",
+                a
+            ),
+        }
+    }
+}
+
+/// A rule is written as an action followed by globs that must all
+/// match, e.g. `hide name=org.freedesktop.secrets` or `read-only
+/// name=org.freedesktop.login1 interface=*.Manager`. A field the rule
+/// doesn't mention matches anything.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    action: Action,
+    name: Option<GlobMatcher>,
+    path: Option<GlobMatcher>,
+    interface: Option<GlobMatcher>,
+    member: Option<GlobMatcher>,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let action = parts
+            .next()
+            .ok_or_else(|| anyhow!("empty rule"))?
+            .parse::<Action>()?;
+        let mut rule = Rule {
+            action,
+            name: None,
+            path: None,
+            interface: None,
+            member: None,
+        };
+        for part in parts {
+            let (key, glob) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected <field>=<glob>, got {}", part))?;
+            let glob = Some(Glob::new(glob)?.compile_matcher());
+            match key {
+                "name" => rule.name = glob,
+                "path" => rule.path = glob,
+                "interface" => rule.interface = glob,
+                "member" => rule.member = glob,
+                k => bail!(
+                    "unknown field {}, expected name, path, interface, or member",
+                    k
+                ),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl Rule {
+    fn matches(
+        &self,
+        name: &str,
+        path: Option<&str>,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> bool {
+        fn is_match(glob: &Option<GlobMatcher>, v: Option<&str>) -> bool {
+            match (glob, v) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(glob), Some(v)) => glob.is_match(v),
+            }
+        }
+        is_match(&self.name, Some(name))
+            && is_match(&self.path, path)
+            && is_match(&self.interface, interface)
+            && is_match(&self.member, member)
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        fn glob(g: &Option<GlobMatcher>) -> Option<&Glob> {
+            g.as_ref().map(|g| g.glob())
+        }
+        self.action == other.action
+            && glob(&self.name) == glob(&other.name)
+            && glob(&self.path) == glob(&other.path)
+            && glob(&self.interface) == glob(&other.interface)
+            && glob(&self.member) == glob(&other.member)
+    }
+}
+
+/// An ordered list of rules, the first rule that matches decides
+/// what happens, and if none match the item is published.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Rules(rules)
+    }
+
+    /// Load rules from a file, one per line. Blank lines and lines
+    /// starting with # are ignored.
+    pub async fn load(file: &Path) -> Result<Vec<Rule>> {
+        let data = fs::read_to_string(file).await?;
+        data.lines()
+            .enumerate()
+            .map(|(i, l)| (i, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+            .map(|(i, l)| {
+                l.parse::<Rule>()
+                    .map_err(|e| anyhow!("{}:{} {}", file.display(), i + 1, e))
+            })
+            .collect()
+    }
+
+    /// Decide what to do with an item. At the bus name level `path`,
+    /// `interface` and `member` are None, and only rules that don't
+    /// mention them apply, and so on down the tree.
+    pub fn check(
+        &self,
+        name: &str,
+        path: Option<&str>,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> Action {
+        self.0
+            .iter()
+            .find(|r| r.matches(name, path, interface, member))
+            .map(|r| r.action)
+            .unwrap_or(Action::Publish)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // name, path, interface, member, and the expected action
+    type Row<'a> = (
+        &'a str,
+        Option<&'a str>,
+        Option<&'a str>,
+        Option<&'a str>,
+        Action,
+    );
+
+    fn rules(rules: &[&str]) -> Rules {
+        Rules::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn check() {
+        let rules = rules(&[
+            "hide name=org.freedesktop.secrets",
+            "hide name=org.example.* path=/org/example/private/**",
+            "read-only name=org.freedesktop.login1 interface=*.Manager",
+            "no-methods member=Power*",
+            "read-only name=org.freedesktop.*",
+            "publish name=org.freedesktop.login1",
+        ]);
+        let o = Some;
+        #[rustfmt::skip]
+        let table: &[Row] = &[
+            ("org.freedesktop.secrets", None, None, None, Action::Hide),
+            ("org.freedesktop.secrets", o("/"), o("a.b"), o("C"), Action::Hide),
+            ("org.example.Foo", None, None, None, Action::Publish),
+            ("org.example.Foo", o("/org/example/private/key"), None, None, Action::Hide),
+            ("org.example.Foo", o("/org/example/public"), None, None, Action::Publish),
+            ("org.freedesktop.login1", None, None, None, Action::ReadOnly),
+            ("org.freedesktop.login1", o("/"), o("org.freedesktop.login1.Manager"), None, Action::ReadOnly),
+            ("org.other", o("/"), o("org.other.Device"), o("PowerOff"), Action::NoMethods),
+            ("org.other", o("/"), o("org.other.Device"), o("Reboot"), Action::Publish),
+            // the first rule that matches wins
+            ("org.freedesktop.UPower", o("/"), o("a.b"), o("PowerOff"), Action::NoMethods),
+            ("org.freedesktop.UPower", o("/"), o("a.b"), o("Suspend"), Action::ReadOnly),
+            ("org.freedesktop.secrets", o("/"), o("a.b"), o("PowerOff"), Action::Hide),
+        ];
+        for (name, path, interface, member, action) in table {
+            let r = rules.check(name, *path, *interface, *member);
+            assert_eq!(
+                r, *action,
+                "{} {:?} {:?} {:?}",
+                name, path, interface, member
+            );
+        }
+        assert_eq!(
+            Rules::default().check("a.b", None, None, None),
+            Action::Publish
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert!("".parse::<Rule>().is_err());
+        assert!("delete name=a".parse::<Rule>().is_err());
+        assert!("hide name".parse::<Rule>().is_err());
+        assert!("hide owner=a".parse::<Rule>().is_err());
+        assert!("hide name=[".parse::<Rule>().is_err());
+        assert_eq!(
+            "hide  name=a".parse::<Rule>().unwrap(),
+            "hide name=a".parse::<Rule>().unwrap()
+        );
+        for (a, writable, methods) in [
+            (Action::Publish, true, true),
+            (Action::ReadOnly, false, false),
+            (Action::NoMethods, true, false),
+            (Action::Hide, false, false),
+        ] {
+            assert_eq!((a.writable(), a.methods()), (writable, methods));
+            assert_eq!(a.hidden(), a == Action::Hide);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use netidx::{
+    path::Path,
+    publisher::{Publisher, Val},
+    subscriber::Value,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    task,
+    time::{self, Instant},
+};
+
+const INTERVAL: Duration = Duration::from_secs(5);
+
+// method call latency histogram buckets, each counts the calls that
+// took less than it's limit, but not less than the previous one's
+const BUCKETS: [(&str, Duration); 5] = [
+    ("1ms", Duration::from_millis(1)),
+    ("10ms", Duration::from_millis(10)),
+    ("100ms", Duration::from_millis(100)),
+    ("1s", Duration::from_secs(1)),
+    ("10s", Duration::from_secs(10)),
+];
+
+/// Things that are counted while they exist
+#[derive(Debug, Clone, Copy)]
+pub enum Gauge {
+    Names,
+    Objects,
+    Properties,
+    Signals,
+    Rpcs,
+    PendingWrites,
+    MatchRules,
+    Starting,
+    Crawling,
+}
+
+const GAUGES: [(Gauge, &str); 9] = [
+    (Gauge::Names, "names"),
+    (Gauge::Objects, "objects"),
+    (Gauge::Properties, "properties"),
+    (Gauge::Signals, "signals"),
+    (Gauge::Rpcs, "rpcs"),
+    (Gauge::PendingWrites, "pending-writes"),
+    (Gauge::MatchRules, "match-rules"),
+    (Gauge::Starting, "starting"),
+    (Gauge::Crawling, "crawling"),
+];
+
+/// Events that are published as a rate per second
+#[derive(Debug, Clone, Copy)]
+pub enum Rate {
+    PropertyUpdates,
+    Signals,
+}
+
+const RATES: [(Rate, &str); 2] = [
+    (Rate::PropertyUpdates, "property-updates-per-sec"),
+    (Rate::Signals, "signals-per-sec"),
+];
+
+#[derive(Debug, Default, Clone)]
+struct Calls {
+    count: u64,
+    errors: u64,
+    latency: [u64; BUCKETS.len() + 1],
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    gauges: [AtomicI64; GAUGES.len()],
+    rates: [AtomicU64; RATES.len()],
+    introspect_failures: Mutex<FxHashMap<String, u64>>,
+    calls: Mutex<FxHashMap<String, Calls>>,
+    last_resync: Mutex<Option<DateTime<Utc>>>,
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl Inner {
+    fn gauge(&self, g: Gauge) -> &AtomicI64 {
+        &self.gauges[g as usize]
+    }
+}
+
+/// Counts one of a gauge until it is dropped
+#[derive(Debug)]
+pub struct Held(Arc<Inner>, Gauge);
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.0.gauge(self.1).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Statistics about the bridge itself, published under `base` every
+/// few seconds
+#[derive(Debug, Clone)]
+pub struct Stats(Arc<Inner>);
+
+impl Stats {
+    pub fn new(publisher: Publisher, base: Path, timeout: Option<Duration>) -> Self {
+        let inner = Arc::new(Inner {
+            timeout: Mutex::new(timeout),
+            ..Inner::default()
+        });
+        task::spawn(Self::run(publisher, base, Arc::downgrade(&inner)));
+        Stats(inner)
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.0.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Count one of `g` until the returned value is dropped
+    pub fn hold(&self, g: Gauge) -> Held {
+        self.add(g, 1);
+        Held(Arc::clone(&self.0), g)
+    }
+
+    pub fn get(&self, g: Gauge) -> i64 {
+        self.0.gauge(g).load(Ordering::Relaxed)
+    }
+
+    pub fn add(&self, g: Gauge, n: i64) {
+        self.0.gauge(g).fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn tick(&self, r: Rate, n: u64) {
+        self.0.rates[r as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Count an introspection failure. Errors from dbus are counted by
+    /// their name, anything else is bad introspection data.
+    pub fn introspect_failed(&self, e: &anyhow::Error) {
+        let reason = match e.downcast_ref::<dbus::Error>() {
+            Some(e) => e.name().unwrap_or("unknown dbus error"),
+            None => "invalid introspection data",
+        };
+        let mut failures = self.0.introspect_failures.lock().unwrap();
+        *failures.entry(String::from(reason)).or_insert(0) += 1;
+    }
+
+    /// Count a method call on `interface` that started at `start`
+    pub fn call(&self, interface: &str, start: Instant, failed: bool) {
+        let elapsed = start.elapsed();
+        let mut calls = self.0.calls.lock().unwrap();
+        let calls = calls.entry(String::from(interface)).or_default();
+        calls.count += 1;
+        if failed {
+            calls.errors += 1;
+        }
+        let bucket = BUCKETS
+            .iter()
+            .position(|(_, limit)| elapsed < *limit)
+            .unwrap_or(BUCKETS.len());
+        calls.latency[bucket] += 1;
+    }
+
+    /// Note that a bus name was just crawled from scratch
+    pub fn resynced(&self) {
+        *self.0.last_resync.lock().unwrap() = Some(Utc::now());
+    }
+
+    async fn run(publisher: Publisher, base: Path, inner: Weak<Inner>) {
+        let mut published: FxHashMap<Path, Val> = HashMap::default();
+        let mut last = Instant::now();
+        loop {
+            time::sleep(INTERVAL).await;
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => break,
+            };
+            let elapsed = last.elapsed().as_secs_f64();
+            last = Instant::now();
+            let mut stats = Vec::new();
+            for (g, name) in GAUGES.iter() {
+                let n = inner.gauge(*g).load(Ordering::Relaxed);
+                stats.push((base.append(name), Value::from(n)));
+            }
+            for (r, name) in RATES.iter() {
+                let n = inner.rates[*r as usize].swap(0, Ordering::Relaxed);
+                stats.push((base.append(name), Value::from(n as f64 / elapsed)));
+            }
+            let last_resync = match *inner.last_resync.lock().unwrap() {
+                None => Value::Null,
+                Some(t) => Value::DateTime(t),
+            };
+            stats.push((base.append("last-resync"), last_resync));
+            let failures = base.append("introspect-failures");
+            for (reason, n) in inner.introspect_failures.lock().unwrap().iter() {
+                stats.push((failures.append(reason), Value::from(*n)));
+            }
+            for (interface, calls) in inner.calls.lock().unwrap().iter() {
+                let base = base.append("calls").append(interface);
+                stats.push((base.append("count"), Value::from(calls.count)));
+                stats.push((base.append("errors"), Value::from(calls.errors)));
+                let names = BUCKETS.iter().map(|(name, _)| *name).chain(["more"]);
+                for (name, n) in names.zip(calls.latency.iter()) {
+                    stats.push((base.append("latency").append(name), Value::from(*n)));
+                }
+            }
+            let timeout = *inner.timeout.lock().unwrap();
+            drop(inner);
+            let mut batch = publisher.start_batch();
+            for (path, v) in stats {
+                match published.get(&path) {
+                    Some(val) => val.update_changed(&mut batch, v),
+                    // after the base changes the stats of the old base
+                    // may still be published for a moment, so this is
+                    // just tried again next time
+                    None => {
+                        if let Ok(val) = publisher.publish(path.clone(), v) {
+                            published.insert(path, val);
+                        }
+                    }
+                }
+            }
+            batch.commit(timeout).await
+        }
+    }
+}
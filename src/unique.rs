@@ -0,0 +1,363 @@
+use crate::{connection_unix_process_id, get_name_owner, Ctx, NameOwnerChanged, ProxiedBusName};
+use anyhow::{anyhow, bail, Result};
+use dbus::nonblock::{Proxy, SyncConnection};
+use futures::future;
+use fxhash::FxHashMap;
+use globset::{Glob, GlobMatcher};
+use log::{info, warn};
+use netidx::{path::Path, publisher::Val, subscriber::Value};
+use std::{collections::BTreeSet, ffi::CStr, iter, mem::MaybeUninit, ptr, str::FromStr, sync::Arc};
+use tokio::{fs, task};
+
+/// Limits the unique names that are bridged by the process or user
+/// that owns them, written as `process=<glob>` or `user=<glob>`
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Process(GlobMatcher),
+    User(GlobMatcher),
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, glob) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected process=<glob> or user=<glob>"))?;
+        let glob = Glob::new(glob)?.compile_matcher();
+        match key {
+            "process" => Ok(Filter::Process(glob)),
+            "user" => Ok(Filter::User(glob)),
+            k => bail!("unknown filter {}, expected process or user", k),
+        }
+    }
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Filter::Process(a), Filter::Process(b)) | (Filter::User(a), Filter::User(b)) => {
+                a.glob() == b.glob()
+            }
+            (_, _) => false,
+        }
+    }
+}
+
+async fn connection_unix_user(con: &Proxy<'_, Arc<SyncConnection>>, name: &str) -> Result<u32> {
+    let (uid,): (u32,) = con
+        .method_call("org.freedesktop.DBus", "GetConnectionUnixUser", (name,))
+        .await?;
+    Ok(uid)
+}
+
+/// Look up the name of `uid` through the system's name service, so
+/// users that aren't in /etc/passwd, e.g. from LDAP, are found too.
+/// That can block, so it is done on the blocking pool.
+async fn user_name(uid: u32) -> Option<String> {
+    task::spawn_blocking(move || {
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut buf = vec![0 as libc::c_char; 1024];
+        let mut result = ptr::null_mut();
+        loop {
+            let r = unsafe {
+                libc::getpwuid_r(
+                    uid,
+                    pwd.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            };
+            match r {
+                libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                0 if !result.is_null() => {
+                    // pw_name points into buf, which is still alive
+                    let name = unsafe { CStr::from_ptr((*result).pw_name) };
+                    break Some(name.to_string_lossy().into_owned());
+                }
+                _ => break None,
+            }
+        }
+    })
+    .await
+    .ok()?
+}
+
+/// The process and user that own a unique name
+struct Owner {
+    pid: Option<u32>,
+    uid: Option<u32>,
+    process: Option<String>,
+    user: Option<String>,
+}
+
+impl Owner {
+    async fn new(dbus: &Proxy<'_, Arc<SyncConnection>>, name: &str) -> Self {
+        let pid = connection_unix_process_id(dbus, name).await.ok();
+        let uid = connection_unix_user(dbus, name).await.ok();
+        let process = match pid {
+            None => None,
+            Some(pid) => fs::read_to_string(format!("/proc/{}/comm", pid))
+                .await
+                .ok()
+                .map(|s| String::from(s.trim_end())),
+        };
+        let user = match uid {
+            None => None,
+            Some(uid) => user_name(uid).await,
+        };
+        Owner {
+            pid,
+            uid,
+            process,
+            user,
+        }
+    }
+
+    fn matches(&self, filters: &[Filter]) -> bool {
+        let is_match = |glob: &GlobMatcher, v: &Option<String>| match v {
+            None => false,
+            Some(v) => glob.is_match(v),
+        };
+        filters.is_empty()
+            || filters.iter().any(|f| match f {
+                Filter::Process(glob) => is_match(glob, &self.process),
+                Filter::User(glob) => is_match(glob, &self.user),
+            })
+    }
+}
+
+struct Unique {
+    tree: ProxiedBusName,
+    names: Val,
+    owned: BTreeSet<String>,
+    meta: Vec<Val>,
+}
+
+impl Unique {
+    fn names(&self) -> Value {
+        Value::from(self.owned.iter().cloned().collect::<Vec<_>>())
+    }
+
+    async fn shutdown(self, ctx: &Ctx) {
+        self.tree.shutdown().await;
+        ctx.retire(iter::once(self.names).chain(self.meta))
+    }
+}
+
+/// Proxies the unique names on a bus under `base`, along with the
+/// process and user that own them, and the well known names they
+/// own.
+pub struct Uniques {
+    ctx: Ctx,
+    con: Arc<SyncConnection>,
+    base: Path,
+    filters: Arc<Vec<Filter>>,
+    // well known name -> unique name of it's owner
+    owners: FxHashMap<String, String>,
+    // unique name -> the process and user behind it, including names
+    // that aren't proxied, so the rules can change without asking again
+    known: FxHashMap<String, Arc<Owner>>,
+    uniques: FxHashMap<String, Unique>,
+}
+
+impl Uniques {
+    /// `names` is every name on the bus, well known and unique
+    pub async fn new(
+        ctx: Ctx,
+        con: Arc<SyncConnection>,
+        base: Path,
+        filters: Arc<Vec<Filter>>,
+        names: &[String],
+    ) -> Self {
+        // the introspection cache is keyed by name, unique names are
+        // never reused
+        let ctx = Ctx { cache: None, ..ctx };
+        let dbus = Proxy::new("org.freedesktop.DBus", "/", ctx.timing.call, con.clone());
+        let owners = future::join_all(
+            names
+                .iter()
+                .filter(|n| !n.starts_with(":") && n.as_str() != "org.freedesktop.DBus")
+                .map(|n| {
+                    let dbus = &dbus;
+                    async move { (n.clone(), get_name_owner(dbus, n).await) }
+                }),
+        )
+        .await
+        .into_iter()
+        .filter_map(|(n, r)| r.ok().map(|o| (n, o)))
+        .collect();
+        let mut t = Uniques {
+            ctx,
+            con,
+            base,
+            filters,
+            owners,
+            known: FxHashMap::default(),
+            uniques: FxHashMap::default(),
+        };
+        let wanted = names
+            .iter()
+            .filter(|n| n.starts_with(":") && t.allowed(n))
+            .collect::<Vec<_>>();
+        t.known = future::join_all(wanted.iter().map(|n| {
+            let dbus = &dbus;
+            async move { (String::from(*n), Arc::new(Owner::new(dbus, n).await)) }
+        }))
+        .await
+        .into_iter()
+        .collect();
+        let started = {
+            let t = &t;
+            future::join_all(wanted.iter().map(|n| async move {
+                let owner = &t.known[n.as_str()];
+                (String::from(*n), t.proxy(n, owner, false).await)
+            }))
+            .await
+        };
+        for (name, unique) in started {
+            if let Some(unique) = unique {
+                t.uniques.insert(name, unique);
+            }
+        }
+        t
+    }
+
+    /// Whether `name` may be proxied, before looking at it's owner
+    fn allowed(&self, name: &str) -> bool {
+        &*self.con.unique_name() != name
+            && !self.ctx.policy().check(name, None, None, None).hidden()
+    }
+
+    /// The owner of the unique name `name`. It can't change, so it is
+    /// only looked up the first time.
+    async fn owner(&mut self, name: &str) -> Arc<Owner> {
+        if let Some(owner) = self.known.get(name) {
+            return owner.clone();
+        }
+        let timeout = self.ctx.timing.call;
+        let dbus = Proxy::new("org.freedesktop.DBus", "/", timeout, self.con.clone());
+        let owner = Arc::new(Owner::new(&dbus, name).await);
+        self.known.insert(String::from(name), owner.clone());
+        owner
+    }
+
+    async fn proxy(&self, name: &str, owner: &Owner, settle: bool) -> Option<Unique> {
+        if !owner.matches(&self.filters) {
+            return None;
+        }
+        let base = self.base.append(name);
+        let tree = ProxiedBusName::new(
+            self.ctx.clone(),
+            &self.con,
+            base.clone(),
+            String::from(name),
+            settle,
+        );
+        let meta = [
+            ("pid", owner.pid.map(Value::from)),
+            ("uid", owner.uid.map(Value::from)),
+            ("process", owner.process.clone().map(Value::from)),
+            ("user", owner.user.clone().map(Value::from)),
+        ]
+        .into_iter()
+        .filter_map(|(n, v)| match self.ctx.publish(base.append(n), v?) {
+            Ok(val) => Some(val),
+            Err(e) => {
+                warn!("failed to publish {} of {} {}", n, name, e);
+                None
+            }
+        })
+        .collect();
+        let owned = self
+            .owners
+            .iter()
+            .filter(|(_, o)| o.as_str() == name)
+            .map(|(n, _)| n.clone())
+            .collect::<BTreeSet<_>>();
+        let names = Value::from(owned.iter().cloned().collect::<Vec<_>>());
+        let names = match self.ctx.publish(base.append("names"), names) {
+            Ok(val) => val,
+            Err(e) => {
+                warn!("failed to publish names of {} {}", name, e);
+                tree.shutdown().await;
+                return None;
+            }
+        };
+        Some(Unique {
+            tree,
+            names,
+            owned,
+            meta,
+        })
+    }
+
+    fn update_names(&mut self, unique: &str, f: impl FnOnce(&mut BTreeSet<String>)) {
+        if let Some(u) = self.uniques.get_mut(unique) {
+            f(&mut u.owned);
+            let mut batch = self.ctx.publisher.start_batch();
+            u.names.update(&mut batch, u.names());
+            tokio::task::spawn(batch.commit(self.ctx.timeout));
+        }
+    }
+
+    pub async fn name_owner_changed(&mut self, up: &NameOwnerChanged) {
+        if up.name.starts_with(":") {
+            if up.new_owner.is_none() {
+                self.known.remove(&up.name);
+                if let Some(u) = self.uniques.remove(&up.name) {
+                    u.shutdown(&self.ctx).await
+                }
+            } else if up.old_owner.is_none() && self.allowed(&up.name) {
+                let owner = self.owner(&up.name).await;
+                if let Some(u) = self.proxy(&up.name, &owner, true).await {
+                    info!("proxying unique name {}", up.name);
+                    self.uniques.insert(up.name.clone(), u);
+                }
+            }
+        } else {
+            if let Some(old) = self.owners.remove(&up.name) {
+                self.update_names(&old, |owned| {
+                    owned.remove(&up.name);
+                })
+            }
+            if let Some(new) = &up.new_owner {
+                self.owners.insert(up.name.clone(), new.clone());
+                self.update_names(new, |owned| {
+                    owned.insert(up.name.clone());
+                })
+            }
+        }
+    }
+
+    /// Bring the proxied unique names in line with the rules after
+    /// they changed. `names` is every name on the bus. The owners of
+    /// names seen before aren't looked up again.
+    pub async fn sync(&mut self, names: &[String]) {
+        let hidden = self
+            .uniques
+            .keys()
+            .filter(|n| self.ctx.policy().check(n, None, None, None).hidden())
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in hidden {
+            if let Some(u) = self.uniques.remove(&name) {
+                u.shutdown(&self.ctx).await
+            }
+        }
+        for name in names.iter().filter(|n| n.starts_with(":")) {
+            if !self.uniques.contains_key(name) && self.allowed(name) {
+                let owner = self.owner(name).await;
+                if let Some(u) = self.proxy(name, &owner, false).await {
+                    self.uniques.insert(name.clone(), u);
+                }
+            }
+        }
+    }
+
+    pub async fn shutdown(self) {
+        let ctx = &self.ctx;
+        future::join_all(self.uniques.into_values().map(|u| u.shutdown(ctx))).await;
+    }
+}
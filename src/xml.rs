@@ -1,9 +1,19 @@
 // This code is based on zbus::xml, but it has been significantly
 // modified
 
-use anyhow::Result;
-use serde_xml_rs::{from_reader, from_str};
-use std::io::Read;
+use anyhow::{anyhow, Result};
+use quick_xml::{
+    events::{BytesStart, Event},
+    name::{Namespace, ResolveResult},
+    NsReader,
+};
+use std::fmt::{self, Write as _};
+
+const DOCTYPE: &str =
+    "<!DOCTYPE node PUBLIC \"-//freedesktop//DTD D-BUS Object Introspection 1.0//EN\"
+ \"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd\">";
+
+const DOC_NS: &str = "http://www.freedesktop.org/dbus/1.0/doc.dtd";
 
 macro_rules! get_vec {
     ($vec:expr, $kind:path) => {
@@ -13,37 +23,409 @@ macro_rules! get_vec {
     };
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Node,
+    Interface,
+    Method,
+    Signal,
+    Property,
+    Arg,
+    Annotation,
+    Doc,
+    Other,
+}
+
+impl Tag {
+    /// Doc elements are found by their namespace, whatever it's prefix
+    /// is. Documents often use the doc prefix without declaring it, so
+    /// that is accepted too. Other elements with a namespace prefix
+    /// are never introspection elements.
+    fn of(ns: &ResolveResult, e: &BytesStart) -> Tag {
+        let name = e.name();
+        let local = name.local_name();
+        let doc = match ns {
+            ResolveResult::Bound(Namespace(ns)) => *ns == DOC_NS.as_bytes(),
+            ResolveResult::Unknown(prefix) => prefix == b"doc",
+            ResolveResult::Unbound => false,
+        };
+        if doc {
+            return match local.as_ref() {
+                b"doc" => Tag::Doc,
+                _ => Tag::Other,
+            };
+        }
+        if name.prefix().is_some() {
+            return Tag::Other;
+        }
+        match local.as_ref() {
+            b"node" => Tag::Node,
+            b"interface" => Tag::Interface,
+            b"method" => Tag::Method,
+            b"signal" => Tag::Signal,
+            b"property" => Tag::Property,
+            b"arg" => Tag::Arg,
+            b"annotation" => Tag::Annotation,
+            _ => Tag::Other,
+        }
+    }
+}
+
+/// A streaming parser for introspection documents. It borrows from
+/// the source document, so the only allocations are for the strings
+/// that end up in the model, which owns them.
+struct Parser<'a> {
+    src: &'a str,
+    reader: NsReader<&'a [u8]>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        let mut reader = NsReader::from_str(src);
+        reader.trim_text(true);
+        Parser { src, reader }
+    }
+
+    /// Annotate the error with the line and column of the current
+    /// position in the document
+    fn error<E: fmt::Display>(&self, e: E) -> anyhow::Error {
+        let pos = self.reader.buffer_position().min(self.src.len());
+        let before = &self.src.as_bytes()[..pos];
+        let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+        let column = pos
+            - before
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map(|i| i + 1)
+                .unwrap_or(0)
+            + 1;
+        anyhow!("{} at line {} column {}", e, line, column)
+    }
+
+    fn next(&mut self) -> Result<Event<'a>> {
+        self.reader.read_event().map_err(|e| self.error(e))
+    }
+
+    /// The next event, and the tag of the element if it starts one
+    fn next_tagged(&mut self) -> Result<(Tag, Event<'a>)> {
+        match self.reader.read_resolved_event() {
+            Ok((ns, e)) => {
+                let tag = match &e {
+                    Event::Start(s) | Event::Empty(s) => Tag::of(&ns, s),
+                    _ => Tag::Other,
+                };
+                Ok((tag, e))
+            }
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    /// Skip the element that was just started, including all it's
+    /// children. The events are read one by one so the namespaces
+    /// declared inside it go out of scope with it.
+    fn skip(&mut self, empty: bool) -> Result<()> {
+        if empty {
+            return Ok(());
+        }
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => break Ok(()),
+                Event::End(_) => depth -= 1,
+                Event::Eof => break Err(self.error("unexpected end of document")),
+                _ => (),
+            }
+        }
+    }
+
+    /// Read the named attributes of e. Attributes with a namespace
+    /// prefix are ignored.
+    fn attrs<const N: usize>(
+        &self,
+        e: &BytesStart,
+        names: [&str; N],
+    ) -> Result<[Option<String>; N]> {
+        const NONE: Option<String> = None;
+        let mut res = [NONE; N];
+        for a in e.attributes() {
+            let a = a.map_err(|e| self.error(e))?;
+            if let Some(i) = names.iter().position(|n| n.as_bytes() == a.key.as_ref()) {
+                res[i] = Some(a.unescape_value().map_err(|e| self.error(e))?.into_owned());
+            }
+        }
+        Ok(res)
+    }
+
+    fn required(&self, e: &BytesStart, name: &str, v: Option<String>) -> Result<String> {
+        v.ok_or_else(|| {
+            let tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+            self.error(format!("{} is missing required attribute {}", tag, name))
+        })
+    }
+
+    /// Call f for each child element of the element that was just
+    /// started, f must consume the child entirely. Text is ignored.
+    fn children<F>(&mut self, empty: bool, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, Tag, BytesStart<'a>, bool) -> Result<()>,
+    {
+        if empty {
+            return Ok(());
+        }
+        loop {
+            match self.next_tagged()? {
+                (tag, Event::Start(e)) => f(self, tag, e, false)?,
+                (tag, Event::Empty(e)) => f(self, tag, e, true)?,
+                (_, Event::End(_)) => break Ok(()),
+                (_, Event::Eof) => break Err(self.error("unexpected end of document")),
+                (_, _) => (),
+            }
+        }
+    }
+
+    /// Read the text of the doc element that was just started. Each
+    /// block of text, e.g. the summary and each paragraph, will be on
+    /// it's own line.
+    fn doc(&mut self, empty: bool) -> Result<Option<String>> {
+        if empty {
+            return Ok(None);
+        }
+        let mut buf = Vec::new();
+        let mut push = |t: &str| {
+            let t = t.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !t.is_empty() {
+                buf.push(t)
+            }
+        };
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => break,
+                Event::End(_) => depth -= 1,
+                Event::Text(t) => push(&t.unescape().map_err(|e| self.error(e))?),
+                Event::CData(t) => push(&String::from_utf8_lossy(&t)),
+                Event::Eof => return Err(self.error("unexpected end of document")),
+                _ => (),
+            }
+        }
+        Ok(if buf.is_empty() {
+            None
+        } else {
+            Some(buf.join("\n"))
+        })
+    }
+
+    /// Parse the document, which must have a node as it's root
+    /// element. Anything after the root element is ignored.
+    fn parse(mut self) -> Result<Node> {
+        loop {
+            match self.next_tagged()? {
+                (Tag::Node, Event::Start(e)) => break Node::parse(&mut self, &e, false),
+                (Tag::Node, Event::Empty(e)) => break Node::parse(&mut self, &e, true),
+                (_, Event::Start(e)) | (_, Event::Empty(e)) => {
+                    let tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                    break Err(self.error(format!("expected a node element found {}", tag)));
+                }
+                (_, Event::Eof) => break Err(self.error("empty document")),
+                (_, _) => (),
+            }
+        }
+    }
+}
+
+/// A wrapper that escapes xml attribute values when displayed
+struct Escaped<'a>(&'a str);
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn indent(f: &mut String, level: usize) {
+    for _ in 0..level {
+        f.push_str("  ")
+    }
+}
+
+fn write_doc(f: &mut String, level: usize, doc: &str) -> fmt::Result {
+    indent(f, level);
+    f.push_str("<doc:doc>\n");
+    indent(f, level + 1);
+    f.push_str("<doc:description>\n");
+    for para in doc.lines() {
+        indent(f, level + 2);
+        writeln!(f, "<doc:para>{}</doc:para>", Escaped(para))?;
+    }
+    indent(f, level + 1);
+    f.push_str("</doc:description>\n");
+    indent(f, level);
+    f.push_str("</doc:doc>\n");
+    Ok(())
+}
+
+/// Write an element with the given attributes, and either close it
+/// immediately, or write it's doc and children and then close it.
+fn write_elem<'a, T: ToXml + 'a>(
+    f: &mut String,
+    level: usize,
+    tag: &str,
+    attrs: &[(&str, Option<&str>)],
+    doc: Option<&str>,
+    children: impl IntoIterator<Item = &'a T>,
+) -> fmt::Result {
+    indent(f, level);
+    write!(f, "<{}", tag)?;
+    for (k, v) in attrs {
+        if let Some(v) = v {
+            write!(f, " {}=\"{}\"", k, Escaped(v))?;
+        }
+    }
+    let mut children = children.into_iter().peekable();
+    if doc.is_none() && children.peek().is_none() {
+        f.push_str("/>\n");
+    } else {
+        f.push_str(">\n");
+        if let Some(doc) = doc {
+            write_doc(f, level + 1, doc)?;
+        }
+        for c in children {
+            c.write_xml(f, level + 1)?;
+        }
+        indent(f, level);
+        writeln!(f, "</{}>", tag)?;
+    }
+    Ok(())
+}
+
+trait ToXml {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result;
+}
+
 /// Annotations are generic key/value pairs of metadata.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Annotation {
     pub name: String,
     pub value: String,
 }
 
+impl Annotation {
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Annotation {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name, value] = p.attrs(e, ["name", "value"])?;
+        let t = Annotation::new(p.required(e, "name", name)?, p.required(e, "value", value)?);
+        p.skip(empty)?;
+        Ok(t)
+    }
+}
+
+impl ToXml for Annotation {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [("name", Some(&*self.name)), ("value", Some(&*self.value))];
+        write_elem::<Annotation>(f, level, "annotation", &attrs, None, [])
+    }
+}
+
 /// An argument
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Arg {
     pub name: Option<String>,
-    #[serde(rename = "type")]
     pub typ: String,
     pub direction: Option<String>,
-    #[serde(rename = "annotation", default)]
     pub annotations: Vec<Annotation>,
+    pub doc: Option<String>,
+}
+
+impl Arg {
+    pub fn new<T: Into<String>>(name: Option<String>, typ: T, direction: Option<String>) -> Self {
+        Arg {
+            name,
+            typ: typ.into(),
+            direction,
+            annotations: vec![],
+            doc: None,
+        }
+    }
+
+    pub fn add_annotation(&mut self, annotation: Annotation) -> &mut Self {
+        self.annotations.push(annotation);
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name, typ, direction] = p.attrs(e, ["name", "type", "direction"])?;
+        let mut t = Arg::new(name, p.required(e, "type", typ)?, direction);
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Annotation => {
+                    t.add_annotation(Annotation::parse(p, &e, empty)?);
+                }
+                Tag::Doc => t.doc = p.doc(empty)?,
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "lowercase")]
+impl ToXml for Arg {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [
+            ("name", self.name.as_deref()),
+            ("type", Some(&*self.typ)),
+            ("direction", self.direction.as_deref()),
+        ];
+        write_elem(
+            f,
+            level,
+            "arg",
+            &attrs,
+            self.doc.as_deref(),
+            &self.annotations,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum MethodElement {
     Arg(Arg),
     Annotation(Annotation),
 }
 
+impl ToXml for MethodElement {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        match self {
+            Self::Arg(a) => a.write_xml(f, level),
+            Self::Annotation(a) => a.write_xml(f, level),
+        }
+    }
+}
+
 /// A method
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Method {
     pub name: String,
+    pub doc: Option<String>,
 
-    #[serde(rename = "$value", default)]
     elems: Vec<MethodElement>,
 }
 
@@ -59,19 +441,73 @@ impl Method {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "lowercase")]
+impl Method {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Method {
+            name: name.into(),
+            doc: None,
+            elems: vec![],
+        }
+    }
+
+    pub fn add_arg(&mut self, arg: Arg) -> &mut Self {
+        self.elems.push(MethodElement::Arg(arg));
+        self
+    }
+
+    pub fn add_annotation(&mut self, annotation: Annotation) -> &mut Self {
+        self.elems.push(MethodElement::Annotation(annotation));
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name] = p.attrs(e, ["name"])?;
+        let mut t = Method::new(p.required(e, "name", name)?);
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Arg => {
+                    t.add_arg(Arg::parse(p, &e, empty)?);
+                }
+                Tag::Annotation => {
+                    t.add_annotation(Annotation::parse(p, &e, empty)?);
+                }
+                Tag::Doc => t.doc = p.doc(empty)?,
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
+}
+
+impl ToXml for Method {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [("name", Some(&*self.name))];
+        write_elem(f, level, "method", &attrs, self.doc.as_deref(), &self.elems)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum SignalElement {
     Arg(Arg),
     Annotation(Annotation),
 }
 
+impl ToXml for SignalElement {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        match self {
+            Self::Arg(a) => a.write_xml(f, level),
+            Self::Annotation(a) => a.write_xml(f, level),
+        }
+    }
+}
+
 /// A signal
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Signal {
     pub name: String,
+    pub doc: Option<String>,
 
-    #[serde(rename = "$value", default)]
     elems: Vec<SignalElement>,
 }
 
@@ -87,19 +523,122 @@ impl Signal {
     }
 }
 
+impl Signal {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Signal {
+            name: name.into(),
+            doc: None,
+            elems: vec![],
+        }
+    }
+
+    pub fn add_arg(&mut self, arg: Arg) -> &mut Self {
+        self.elems.push(SignalElement::Arg(arg));
+        self
+    }
+
+    pub fn add_annotation(&mut self, annotation: Annotation) -> &mut Self {
+        self.elems.push(SignalElement::Annotation(annotation));
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name] = p.attrs(e, ["name"])?;
+        let mut t = Signal::new(p.required(e, "name", name)?);
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Arg => {
+                    t.add_arg(Arg::parse(p, &e, empty)?);
+                }
+                Tag::Annotation => {
+                    t.add_annotation(Annotation::parse(p, &e, empty)?);
+                }
+                Tag::Doc => t.doc = p.doc(empty)?,
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
+}
+
+impl ToXml for Signal {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [("name", Some(&*self.name))];
+        write_elem(f, level, "signal", &attrs, self.doc.as_deref(), &self.elems)
+    }
+}
+
 /// A property
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Property {
     pub name: String,
-    #[serde(rename = "type")]
     pub typ: String,
     pub access: String,
-    #[serde(rename = "annotation", default)]
     pub annotations: Vec<Annotation>,
+    pub doc: Option<String>,
+}
+
+impl Property {
+    pub fn new<N: Into<String>, T: Into<String>, A: Into<String>>(
+        name: N,
+        typ: T,
+        access: A,
+    ) -> Self {
+        Property {
+            name: name.into(),
+            typ: typ.into(),
+            access: access.into(),
+            annotations: vec![],
+            doc: None,
+        }
+    }
+
+    pub fn add_annotation(&mut self, annotation: Annotation) -> &mut Self {
+        self.annotations.push(annotation);
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name, typ, access] = p.attrs(e, ["name", "type", "access"])?;
+        let mut t = Property::new(
+            p.required(e, "name", name)?,
+            p.required(e, "type", typ)?,
+            p.required(e, "access", access)?,
+        );
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Annotation => {
+                    t.add_annotation(Annotation::parse(p, &e, empty)?);
+                }
+                Tag::Doc => t.doc = p.doc(empty)?,
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "lowercase")]
+impl ToXml for Property {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [
+            ("name", Some(&*self.name)),
+            ("type", Some(&*self.typ)),
+            ("access", Some(&*self.access)),
+        ];
+        write_elem(
+            f,
+            level,
+            "property",
+            &attrs,
+            self.doc.as_deref(),
+            &self.annotations,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum InterfaceElement {
     Method(Method),
     Signal(Signal),
@@ -107,12 +646,23 @@ enum InterfaceElement {
     Annotation(Annotation),
 }
 
+impl ToXml for InterfaceElement {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        match self {
+            Self::Method(m) => m.write_xml(f, level),
+            Self::Signal(s) => s.write_xml(f, level),
+            Self::Property(p) => p.write_xml(f, level),
+            Self::Annotation(a) => a.write_xml(f, level),
+        }
+    }
+}
+
 /// An interface
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Interface {
     pub name: String,
+    pub doc: Option<String>,
 
-    #[serde(rename = "$value", default)]
     elems: Vec<InterfaceElement>,
 }
 
@@ -128,7 +678,7 @@ impl Interface {
     }
 
     /// Returns the interface properties.
-    pub fn _properties(&self) -> Vec<&Property> {
+    pub fn properties(&self) -> Vec<&Property> {
         get_vec!(self.elems, InterfaceElement::Property)
     }
 
@@ -138,28 +688,113 @@ impl Interface {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "lowercase")]
+impl Interface {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Interface {
+            name: name.into(),
+            doc: None,
+            elems: vec![],
+        }
+    }
+
+    pub fn add_method(&mut self, method: Method) -> &mut Self {
+        self.elems.push(InterfaceElement::Method(method));
+        self
+    }
+
+    pub fn add_signal(&mut self, signal: Signal) -> &mut Self {
+        self.elems.push(InterfaceElement::Signal(signal));
+        self
+    }
+
+    pub fn add_property(&mut self, property: Property) -> &mut Self {
+        self.elems.push(InterfaceElement::Property(property));
+        self
+    }
+
+    pub fn add_annotation(&mut self, annotation: Annotation) -> &mut Self {
+        self.elems.push(InterfaceElement::Annotation(annotation));
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name] = p.attrs(e, ["name"])?;
+        let mut t = Interface::new(p.required(e, "name", name)?);
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Method => {
+                    t.add_method(Method::parse(p, &e, empty)?);
+                }
+                Tag::Signal => {
+                    t.add_signal(Signal::parse(p, &e, empty)?);
+                }
+                Tag::Property => {
+                    t.add_property(Property::parse(p, &e, empty)?);
+                }
+                Tag::Annotation => {
+                    t.add_annotation(Annotation::parse(p, &e, empty)?);
+                }
+                Tag::Doc => t.doc = p.doc(empty)?,
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
+
+    fn has_doc(&self) -> bool {
+        self.doc.is_some()
+            || self.elems.iter().any(|e| match e {
+                InterfaceElement::Method(m) => {
+                    m.doc.is_some() || m.args().iter().any(|a| a.doc.is_some())
+                }
+                InterfaceElement::Signal(s) => {
+                    s.doc.is_some() || s.args().iter().any(|a| a.doc.is_some())
+                }
+                InterfaceElement::Property(p) => p.doc.is_some(),
+                InterfaceElement::Annotation(_) => false,
+            })
+    }
+}
+
+impl ToXml for Interface {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        let attrs = [("name", Some(&*self.name))];
+        write_elem(
+            f,
+            level,
+            "interface",
+            &attrs,
+            self.doc.as_deref(),
+            &self.elems,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum NodeElement {
     Node(Node),
     Interface(Interface),
 }
 
+impl ToXml for NodeElement {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        match self {
+            Self::Node(n) => n.write_xml(f, level),
+            Self::Interface(i) => i.write_xml(f, level),
+        }
+    }
+}
+
 /// A node in the introspection tree
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Node {
     pub name: Option<String>,
 
-    #[serde(rename = "$value", default)]
     elems: Vec<NodeElement>,
 }
 
 impl Node {
-    /// Parse the introspection XML document from reader.
-    pub fn from_reader<R: Read>(reader: R) -> Result<Node> {
-        Ok(from_reader(reader)?)
-    }
-
     /// Returns the children nodes.
     pub fn nodes(&self) -> Vec<&Node> {
         get_vec!(self.elems, NodeElement::Node)
@@ -169,13 +804,211 @@ impl Node {
     pub fn interfaces(&self) -> Vec<&Interface> {
         get_vec!(self.elems, NodeElement::Interface)
     }
+
+    /// Return the introspection XML document, including the DOCTYPE
+    /// header, as a string.
+    pub fn to_xml(&self) -> String {
+        let mut s = String::with_capacity(256);
+        s.push_str(DOCTYPE);
+        s.push('\n');
+        // writing to a string can't fail
+        let _ = self.write_root(&mut s);
+        s
+    }
+
+    fn write_root(&self, f: &mut String) -> fmt::Result {
+        if !self.has_doc() {
+            self.write_xml(f, 0)
+        } else {
+            // the doc namespace must be declared on the root
+            let attrs = [("name", self.name.as_deref()), ("xmlns:doc", Some(DOC_NS))];
+            write_elem(f, 0, "node", &attrs, None, &self.elems)
+        }
+    }
+
+    fn has_doc(&self) -> bool {
+        self.elems.iter().any(|e| match e {
+            NodeElement::Node(n) => n.has_doc(),
+            NodeElement::Interface(i) => i.has_doc(),
+        })
+    }
+}
+
+impl Node {
+    pub fn new(name: Option<String>) -> Self {
+        Node {
+            name,
+            elems: vec![],
+        }
+    }
+
+    pub fn add_node(&mut self, node: Node) -> &mut Self {
+        self.elems.push(NodeElement::Node(node));
+        self
+    }
+
+    pub fn add_interface(&mut self, interface: Interface) -> &mut Self {
+        self.elems.push(NodeElement::Interface(interface));
+        self
+    }
+
+    fn parse(p: &mut Parser, e: &BytesStart, empty: bool) -> Result<Self> {
+        let [name] = p.attrs(e, ["name"])?;
+        let mut t = Node::new(name);
+        p.children(empty, |p, tag, e, empty| {
+            match tag {
+                Tag::Node => {
+                    t.add_node(Node::parse(p, &e, empty)?);
+                }
+                Tag::Interface => {
+                    t.add_interface(Interface::parse(p, &e, empty)?);
+                }
+                _ => p.skip(empty)?,
+            }
+            Ok(())
+        })?;
+        Ok(t)
+    }
+}
+
+impl ToXml for Node {
+    fn write_xml(&self, f: &mut String, level: usize) -> fmt::Result {
+        write_elem(
+            f,
+            level,
+            "node",
+            &[("name", self.name.as_deref())],
+            None,
+            &self.elems,
+        )
+    }
 }
 
 impl std::str::FromStr for Node {
     type Err = anyhow::Error;
 
-    /// Parse the introspection XML document from `s`.
+    /// Parse the introspection XML document from `s`, borrowing from
+    /// it while parsing. Elements that are not part of the
+    /// introspection format, including vendor extensions, are
+    /// ignored. Doc elements are kept.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(from_str(s)?)
+        Parser::new(s).parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(doc: &str) -> Node {
+        let node = doc.parse::<Node>().unwrap();
+        let xml = node.to_xml();
+        assert_eq!(xml, xml.parse::<Node>().unwrap().to_xml());
+        node
+    }
+
+    #[test]
+    fn builder() {
+        let mut m = Method::new("Frob");
+        m.add_arg(Arg::new(Some("widget".into()), "s", Some("in".into())))
+            .add_arg(Arg::new(None, "b", Some("out".into())))
+            .add_annotation(Annotation::new("org.freedesktop.DBus.Deprecated", "true"));
+        let mut s = Signal::new("Frobbed");
+        s.add_arg(Arg::new(Some("widget".into()), "s", None));
+        let mut p = Property::new("Name", "s", "read");
+        p.add_annotation(Annotation::new("a&b", "<\"'>"));
+        let mut i = Interface::new("org.example.Widget");
+        i.add_method(m).add_signal(s).add_property(p);
+        let mut node = Node::new(None);
+        node.add_interface(i)
+            .add_node(Node::new(Some("child".into())));
+        let xml = node.to_xml();
+        let again = xml.parse::<Node>().unwrap();
+        assert_eq!(xml, again.to_xml());
+        let i = again.interfaces()[0];
+        assert_eq!(i.name, "org.example.Widget");
+        assert_eq!(i.methods()[0].args().len(), 2);
+        assert_eq!(i.signals()[0].name, "Frobbed");
+        assert_eq!(i.properties()[0].annotations[0].value, "<\"'>");
+        assert_eq!(again.nodes()[0].name.as_deref(), Some("child"));
+    }
+
+    #[test]
+    fn corpus() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("benches")
+            .join("corpus");
+        let mut n = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|e| e.to_str()) == Some("xml") {
+                let node = round_trip(&std::fs::read_to_string(&path).unwrap());
+                assert!(!node.interfaces().is_empty(), "{}", path.display());
+                n += 1;
+            }
+        }
+        assert!(n > 0)
+    }
+
+    #[test]
+    fn docs() {
+        let doc = r#"<node xmlns:doc="http://www.freedesktop.org/dbus/1.0/doc.dtd">
+  <interface name="org.example.Docs">
+    <doc:doc><doc:description><doc:para>An interface &amp; it's docs</doc:para></doc:description></doc:doc>
+    <method name="Frob">
+      <doc:doc>
+        <doc:summary>Frob the widget</doc:summary>
+        <doc:description><doc:para>It takes a while.</doc:para></doc:description>
+      </doc:doc>
+      <arg name="widget" type="s" direction="in">
+        <doc:doc><doc:summary>The widget</doc:summary></doc:doc>
+      </arg>
+      <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+    </method>
+    <signal name="Frobbed">
+      <arg name="widget" type="s"/>
+    </signal>
+    <property name="Count" type="u" access="read">
+      <doc:doc><doc:summary>How many</doc:summary></doc:doc>
+    </property>
+  </interface>
+  <node name="child"/>
+</node>"#;
+        let node = round_trip(doc);
+        let i = node.interfaces()[0];
+        assert_eq!(i.doc.as_deref(), Some("An interface & it's docs"));
+        let m = i.methods()[0];
+        assert_eq!(m.doc.as_deref(), Some("Frob the widget\nIt takes a while."));
+        assert_eq!(m.args()[0].doc.as_deref(), Some("The widget"));
+        assert_eq!(m._annotations().len(), 1);
+        assert_eq!(i.properties()[0].doc.as_deref(), Some("How many"));
+        assert_eq!(node.nodes()[0].name.as_deref(), Some("child"));
+    }
+
+    #[test]
+    fn doc_namespace() {
+        let doc = r#"<node xmlns:d="http://www.freedesktop.org/dbus/1.0/doc.dtd">
+  <interface name="org.example.A">
+    <d:doc><d:summary>Found by namespace</d:summary></d:doc>
+  </interface>
+  <interface name="org.example.B" xmlns:doc="urn:example:other">
+    <doc:doc><doc:summary>Some other doc</doc:summary></doc:doc>
+  </interface>
+  <interface name="org.example.C">
+    <doc:doc><doc:summary>Undeclared prefix</doc:summary></doc:doc>
+  </interface>
+  <x:node xmlns:x="urn:example:other" name="vendor"/>
+</node>"#;
+        let node = doc.parse::<Node>().unwrap();
+        let docs = node
+            .interfaces()
+            .into_iter()
+            .map(|i| i.doc.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            docs,
+            [Some("Found by namespace"), None, Some("Undeclared prefix")]
+        );
+        assert!(node.nodes().is_empty());
     }
 }